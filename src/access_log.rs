@@ -0,0 +1,331 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    http::header,
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, bail, Error, Result};
+use ark_core::env;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::config::Overrides;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Off,
+    Common,
+    #[default]
+    Combined,
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(Self::Off),
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown access log format: {s}"),
+        }
+    }
+}
+
+/// Number of the access log lines queued for the writer thread, dropping the overflowing ones
+const QUEUE_SIZE: usize = 4096;
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    writer: Option<SyncSender<String>>,
+}
+
+impl AccessLog {
+    pub fn try_default(overrides: &Overrides) -> Result<Self> {
+        let format = overrides
+            .infer::<String>("ACCESS_LOG_FORMAT")
+            .map(|format| format.parse())
+            .unwrap_or_else(|_| Ok(Default::default()))?;
+
+        let writer = match overrides.infer::<PathBuf>("ACCESS_LOG_FILE") {
+            Ok(path) => Some(
+                RotatingFile::try_new(
+                    path,
+                    env::infer::<_, u64>("ACCESS_LOG_MAX_SIZE_MB").unwrap_or(100) << 20,
+                    env::infer("ACCESS_LOG_MAX_FILES").unwrap_or(5),
+                )?
+                .spawn()?,
+            ),
+            Err(_) => None,
+        };
+
+        Ok(Self { format, writer })
+    }
+
+    /// Attach an access log entry to the response, which is written once the body is completed
+    pub fn wrap(
+        self: &Arc<Self>,
+        req: &HttpRequest,
+        res: HttpResponse,
+        started: Instant,
+    ) -> HttpResponse {
+        if self.format == AccessLogFormat::Off {
+            return res;
+        }
+
+        let header = |key| {
+            req.headers()
+                .get(key)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        let upstream = res.extensions().get::<UpstreamInfo>().cloned();
+
+        let entry = Entry {
+            time: Utc::now(),
+            peer_addr: req
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "-".into()),
+            method: req.method().to_string(),
+            uri: req.uri().to_string(),
+            version: format!("{:?}", req.version()),
+            status: res.status().as_u16(),
+            referer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+            upstream_host: upstream.as_ref().map(|upstream| upstream.host.clone()),
+            upstream_duration_ms: upstream.map(|upstream| as_millis(upstream.elapsed)),
+            duration_ms: Default::default(),
+            bytes: 0,
+        };
+
+        let access_log = self.clone();
+        res.map_body(move |_, body| {
+            BoxBody::new(LoggedBody {
+                inner: body,
+                access_log,
+                entry: Some(entry),
+                started,
+            })
+        })
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = match self.format {
+            AccessLogFormat::Off => return,
+            AccessLogFormat::Common => entry.to_common(),
+            AccessLogFormat::Combined => format!(
+                "{common} \"{referer}\" \"{user_agent}\"",
+                common = entry.to_common(),
+                referer = entry.referer.as_deref().unwrap_or("-"),
+                user_agent = entry.user_agent.as_deref().unwrap_or("-"),
+            ),
+            AccessLogFormat::Json => match ::serde_json::to_string(entry) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("failed to serialize the access log: {e}");
+                    return;
+                }
+            },
+        };
+
+        // NOTE: never block the workers on the file, as this runs on dropping the response body
+        match &self.writer {
+            Some(writer) => match writer.try_send(line) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => warn!("dropped the access log: the queue is full"),
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!("dropped the access log: the writer has stopped")
+                }
+            },
+            None => info!(target: "access_log", "{line}"),
+        }
+    }
+}
+
+/// Upstream information of a proxied response, stored in the response extensions
+#[derive(Clone, Debug)]
+pub struct UpstreamInfo {
+    pub host: String,
+    pub elapsed: Duration,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    time: DateTime<Utc>,
+    peer_addr: String,
+    method: String,
+    uri: String,
+    version: String,
+    status: u16,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    upstream_host: Option<String>,
+    upstream_duration_ms: Option<f64>,
+    duration_ms: f64,
+    bytes: u64,
+}
+
+impl Entry {
+    fn to_common(&self) -> String {
+        let Self {
+            time,
+            peer_addr,
+            method,
+            uri,
+            version,
+            status,
+            bytes,
+            ..
+        } = self;
+
+        format!(
+            "{peer_addr} - - [{time}] \"{method} {uri} {version}\" {status} {bytes}",
+            time = time.format("%d/%b/%Y:%H:%M:%S %z"),
+        )
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e3
+}
+
+struct LoggedBody {
+    inner: BoxBody,
+    access_log: Arc<AccessLog>,
+    entry: Option<Entry>,
+    started: Instant,
+}
+
+impl MessageBody for LoggedBody {
+    type Error = Box<dyn ::std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.inner.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_next(cx);
+        if let (Poll::Ready(Some(Ok(chunk))), Some(entry)) = (&poll, &mut this.entry) {
+            entry.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.duration_ms = as_millis(self.started.elapsed());
+            self.access_log.write(&entry);
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn try_new(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self> {
+        let file = Self::open(&path)?;
+        let size = file
+            .metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    /// Write the queued lines on a dedicated thread, including the rotations
+    fn spawn(mut self) -> Result<SyncSender<String>> {
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("access-log".into())
+            .spawn(move || {
+                for line in rx {
+                    if let Err(e) = self.write_line(&line) {
+                        warn!("failed to write the access log: {e}");
+                    }
+                }
+            })
+            .map_err(|e| anyhow!("failed to spawn the access log writer: {e}"))?;
+        Ok(tx)
+    }
+
+    fn open(path: &PathBuf) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("failed to open {path}: {e}", path = path.display()))
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{index}"));
+            PathBuf::from(path)
+        };
+
+        // NOTE: the oldest file is overwritten by the next one
+        for index in (1..self.max_files).rev() {
+            let src = rotated(index);
+            if src.exists() {
+                fs::rename(&src, rotated(index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = Self::open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
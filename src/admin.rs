@@ -0,0 +1,292 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use actix_web::{dev::Server, http::header, web, App, HttpResponse, HttpServer, Responder};
+use anyhow::Result;
+use ark_core::env;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, ConfigMap, Overrides},
+    Context,
+};
+
+/// Reserved path of the liveness probe on the main listener, relative to `BASE_URL`
+pub const HEALTHZ_PATH: &str = "__proxy/healthz";
+
+/// Reserved path of the readiness probe on the main listener, relative to `BASE_URL`
+pub const READYZ_PATH: &str = "__proxy/readyz";
+
+#[derive(Serialize)]
+pub struct AdminConfig {
+    pub addr: SocketAddr,
+    pub readiness_timeout: Duration,
+}
+
+impl AdminConfig {
+    pub fn try_default(overrides: &Overrides) -> Result<Self> {
+        Ok(Self {
+            addr: overrides
+                .infer("ADMIN_BIND_ADDR")
+                .unwrap_or_else(|_| "127.0.0.1:9090".parse().unwrap()),
+            readiness_timeout: Duration::from_secs(
+                env::infer("ADMIN_READINESS_TIMEOUT_SECS").unwrap_or(5),
+            ),
+        })
+    }
+
+    /// Register the probes on the main listener, which the kubelet can reach on the pod IP
+    /// unlike the admin listener bound to the loopback address
+    pub fn probes(&self, base_url: &str) -> impl Fn(&mut web::ServiceConfig) + Clone + Send {
+        let healthz_path = format!("{base_url}{HEALTHZ_PATH}");
+        let readyz_path = format!("{base_url}{READYZ_PATH}");
+        let readiness_timeout = web::Data::new(ReadinessTimeout(self.readiness_timeout));
+
+        move |config| {
+            config
+                .app_data(web::Data::clone(&readiness_timeout))
+                .route(&healthz_path, web::get().to(healthz))
+                .route(&readyz_path, web::get().to(readyz));
+        }
+    }
+
+    pub fn serve(&self, context: web::Data<Context>) -> Server {
+        let addr = self.addr;
+        let readiness_timeout = web::Data::new(ReadinessTimeout(self.readiness_timeout));
+
+        HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::clone(&context))
+                .app_data(web::Data::clone(&readiness_timeout))
+                .route("/config", web::get().to(config))
+                .route("/filters", web::get().to(filters))
+                .route("/filters/debug", web::post().to(debug_filters))
+                .route("/healthz", web::get().to(healthz))
+                .route("/metrics", web::get().to(metrics))
+                .route("/readyz", web::get().to(readyz))
+                .route("/version", web::get().to(version))
+        })
+        .bind(addr)
+        .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"))
+        .shutdown_timeout(20)
+        .run()
+    }
+}
+
+struct ReadinessTimeout(Duration);
+
+async fn config(context: web::Data<Context>) -> impl Responder {
+    HttpResponse::Ok().json(::serde_json::json!({
+        "client": &context.client_config,
+        "config": &context.config_map,
+        "cookies": &context.cookies,
+        "cors": &context.cors,
+        "origins": &context.origins,
+    }))
+}
+
+async fn filters(context: web::Data<Context>) -> impl Responder {
+    #[derive(Serialize)]
+    struct Filter<'a> {
+        index: usize,
+        kind: &'static str,
+        name: &'a str,
+    }
+
+    let request_filters = context
+        .request_filters
+        .iter()
+        .enumerate()
+        .map(|(index, filter)| Filter {
+            index,
+            kind: "request",
+            name: filter.name(),
+        });
+    let url_filters = context
+        .url_filters
+        .iter()
+        .enumerate()
+        .map(|(index, filter)| Filter {
+            index,
+            kind: "url",
+            name: filter.name(),
+        });
+    let response_filters = context
+        .filters
+        .iter()
+        .enumerate()
+        .map(|(index, filter)| Filter {
+            index,
+            kind: "response",
+            name: filter.name(),
+        });
+
+    HttpResponse::Ok().json(
+        request_filters
+            .chain(url_filters)
+            .chain(response_filters)
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[derive(Deserialize)]
+struct DebugFiltersRequest {
+    /// Path under the base URL, e.g. `/page` or `/_origin/cdn.example.com/page`
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    config: HashMap<String, String>,
+}
+
+/// Apply the filters step by step to the given document or the document fetched from the upstream
+///
+/// The URL is a path resolved as the proxy does, so that only the upstream and its origins are fetched.
+async fn debug_filters(
+    context: web::Data<Context>,
+    request: web::Json<DebugFiltersRequest>,
+) -> impl Responder {
+    let DebugFiltersRequest { url, body, config } = request.into_inner();
+
+    let body = match (url, body) {
+        (None, Some(body)) => body,
+        (Some(path), None) => {
+            let (client, url) =
+                match context
+                    .origins
+                    .resolve_url(&context.config, &context.client, &path)
+                {
+                    Ok(resolved) => resolved,
+                    Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+                };
+            match client
+                .get(url.clone())
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+            {
+                Ok(res) => match res.text().await {
+                    Ok(body) => body,
+                    Err(e) => {
+                        return HttpResponse::BadGateway()
+                            .body(format!("failed to read the document ({url}): {e}"))
+                    }
+                },
+                Err(e) => {
+                    return HttpResponse::BadGateway()
+                        .body(format!("failed to fetch the document ({url}): {e}"))
+                }
+            }
+        }
+        _ => return HttpResponse::BadRequest().body("either url or body should be given"),
+    };
+
+    let config_map = debug_config_map(&context.config_map, config);
+    HttpResponse::Ok().json(context.filters.debug(&config_map, body).await)
+}
+
+/// Extend the static config map with the given entries,
+/// filling the other per-request keys with the sample values as the CLI does
+fn debug_config_map(config_map: &ConfigMap, config: HashMap<String, String>) -> ConfigMap {
+    let mut config_map = config_map.clone();
+    config_map.extend(config);
+    config_map.extend_with_samples();
+    config_map
+}
+
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+async fn metrics() -> impl Responder {
+    match crate::metrics::encode() {
+        Ok(body) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, ::prometheus::TEXT_FORMAT))
+            .body(body),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Check whether the upstream is reachable and not failing
+async fn readyz(
+    context: web::Data<Context>,
+    timeout: web::Data<ReadinessTimeout>,
+) -> impl Responder {
+    let Config {
+        proxy_base_url,
+        proxy_host,
+        proxy_scheme,
+        ..
+    } = &context.config;
+    let url = format!("{proxy_scheme}://{proxy_host}{proxy_base_url}");
+
+    match context.client.head(&url).timeout(timeout.0).send().await {
+        Ok(res) if !res.status().is_server_error() => HttpResponse::Ok().body("ok"),
+        Ok(res) => HttpResponse::ServiceUnavailable()
+            .body(format!("upstream is not ready ({url}): {}", res.status())),
+        Err(e) => HttpResponse::ServiceUnavailable()
+            .body(format!("upstream is not reachable ({url}): {e}")),
+    }
+}
+
+async fn version() -> impl Responder {
+    macro_rules! features {
+        ( $( $feature:expr , )* ) => {
+            [ $( cfg!(feature = $feature).then_some($feature) , )* ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+        };
+    }
+
+    HttpResponse::Ok().json(::serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": features!(
+            "compression",
+            "filter-html",
+            "filter-notion",
+            "filter-origin",
+            "filter-script",
+            "filter-wasm",
+            "tls-default",
+            "tls-native",
+            "tls-rustls",
+        ),
+    }))
+}
+
+#[cfg(all(test, feature = "filter-html", feature = "filter-notion"))]
+mod tests {
+    use crate::filters::{DefaultResponseFilter, ResponseFilterBuilder};
+
+    use super::*;
+
+    #[::actix_web::test]
+    async fn debug_filters_on_static_config() {
+        let mut overrides = Overrides::default();
+        overrides.insert("PROXY_HOST", "www.notion.so".into());
+        let config_map = Config::try_default(&overrides).unwrap().to_map();
+        let filters = DefaultResponseFilter.try_build(&config_map).unwrap();
+
+        let config = [("host".to_string(), "proxy.example.com".to_string())];
+        let config_map = debug_config_map(&config_map, config.into_iter().collect());
+        let body = "<html><head></head><body></body></html>";
+        let steps = filters.debug(&config_map, body.into()).await;
+
+        assert_eq!(steps.len(), filters.iter().count());
+        for step in &steps {
+            assert_eq!(step.error, None, "{name}", name = step.name);
+        }
+        let output = &steps.last().unwrap().output;
+        assert!(output.contains(r#"<base href="/">"#), "{output}");
+        assert!(
+            output.contains(r#"data-upstream="https://www.notion.so/""#),
+            "{output}"
+        );
+        assert!(
+            output.contains(r#"data-guard-replace-state="true""#),
+            "{output}"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::ops::Range;
+
+use actix_web::web::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::{self, HeaderValue};
+
+use crate::filters::ResponseParts;
+
+/// Size of the body prefix to sniff `<meta charset>`, as the HTML spec does
+const SNIFF_SIZE: usize = 1024;
+
+/// Decode the body into UTF-8 using the BOM, the declared charset or the sniffed one of HTML,
+/// returning the encoding to restore after filtering.
+pub fn decode(res: &mut ResponseParts) -> &'static Encoding {
+    let mime = res
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<::mime::Mime>().ok());
+
+    let encoding = Encoding::for_bom(&res.body)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| {
+            mime.as_ref()
+                .and_then(|mime| mime.get_param(::mime::CHARSET))
+                .and_then(|charset| Encoding::for_label(charset.as_str().as_bytes()))
+        })
+        // NOTE: the stylesheets and scripts have no `<meta>`, but may contain the string
+        .or_else(|| {
+            mime.as_ref()
+                .filter(|mime| mime.subtype() == ::mime::HTML)
+                .and_then(|_| sniff(&res.body))
+        })
+        .unwrap_or(UTF_8);
+
+    if encoding != UTF_8 {
+        let (body, _) = encoding.decode_with_bom_removal(&res.body);
+        res.body = Bytes::from(body.into_owned());
+    }
+    encoding
+}
+
+/// Encode the UTF-8 body back into the given encoding.
+///
+/// Encodings which cannot be written (e.g. UTF-16) fall back to UTF-8,
+/// updating the charset of the `Content-Type` header and the `<meta>` tag.
+pub fn encode(res: &mut ResponseParts, encoding: &'static Encoding) {
+    if encoding == UTF_8 {
+        return;
+    }
+
+    let mut text = String::from_utf8_lossy(&res.body);
+    let (body, output, _) = encoding.encode(&text);
+    let mut body = Bytes::from(body.into_owned());
+
+    if output != encoding {
+        let mut head_len = text.len().min(SNIFF_SIZE);
+        while !text.is_char_boundary(head_len) {
+            head_len -= 1;
+        }
+        if let Some(range) = meta_charset(&text[..head_len]) {
+            text.to_mut().replace_range(range, output.name());
+        }
+        body = Bytes::from(text.into_owned());
+
+        if let Some(value) = res
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<::mime::Mime>().ok())
+            .and_then(|mime| {
+                HeaderValue::from_str(&format!(
+                    "{type_}/{subtype}; charset={charset}",
+                    type_ = mime.type_(),
+                    subtype = mime.subtype(),
+                    charset = output.name(),
+                ))
+                .ok()
+            })
+        {
+            res.headers.insert(header::CONTENT_TYPE, value);
+        }
+    }
+    res.body = body;
+}
+
+/// Find the charset of `<meta charset>` or `<meta http-equiv="Content-Type">`
+fn sniff(body: &[u8]) -> Option<&'static Encoding> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(SNIFF_SIZE)]);
+    meta_charset(&head).and_then(|range| Encoding::for_label(&head.as_bytes()[range]))
+}
+
+/// Find the range of the charset label in the first `<meta>` tag declaring a known one
+fn meta_charset(head: &str) -> Option<Range<usize>> {
+    let lowercase = head.to_ascii_lowercase();
+
+    lowercase.match_indices("<meta").find_map(|(index, _)| {
+        let tag = &lowercase[index..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let start = index + tag.find("charset=")? + "charset=".len();
+        let charset = lowercase[start..].trim_start_matches(['"', '\'']);
+        let start = lowercase.len() - charset.len();
+        let end = charset
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+            .unwrap_or(charset.len());
+        Encoding::for_label(&charset.as_bytes()[..end]).map(|_| start..start + end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_type: &'static str, body: impl Into<Bytes>) -> ResponseParts {
+        let mut res = ResponseParts::from_html(body);
+        res.headers
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        res
+    }
+
+    #[test]
+    fn sniff_meta_charset() {
+        assert_eq!(
+            sniff(br#"<head><meta charset="Shift_JIS"></head>"#),
+            Some(::encoding_rs::SHIFT_JIS)
+        );
+        assert_eq!(
+            sniff(br#"<meta http-equiv="Content-Type" content="text/html; charset=euc-kr">"#),
+            Some(::encoding_rs::EUC_KR)
+        );
+        assert_eq!(
+            sniff(br#"<meta charset="unknown"><meta charset='iso-8859-2'>"#),
+            Some(::encoding_rs::ISO_8859_2)
+        );
+        assert_eq!(sniff(b"<meta name=charset>"), None);
+    }
+
+    #[test]
+    fn decode_sniffs_only_html() {
+        let body = "<meta charset=\"shift_jis\">\u{3042}";
+        let (encoded, _, _) = ::encoding_rs::SHIFT_JIS.encode(body);
+        let encoded = Bytes::from(encoded.into_owned());
+
+        let mut res = response("text/html", encoded.clone());
+        assert_eq!(decode(&mut res), ::encoding_rs::SHIFT_JIS);
+        assert_eq!(res.body, body);
+
+        let mut res = response("application/javascript", encoded.clone());
+        assert_eq!(decode(&mut res), UTF_8);
+        assert_eq!(res.body, encoded);
+    }
+
+    #[test]
+    fn encode_restores_the_encoding() {
+        let mut res = response("text/html; charset=shift_jis", "\u{3042}");
+        encode(&mut res, ::encoding_rs::SHIFT_JIS);
+        assert_eq!(res.body, &b"\x82\xa0"[..]);
+        assert_eq!(
+            res.headers[header::CONTENT_TYPE],
+            "text/html; charset=shift_jis"
+        );
+    }
+
+    #[test]
+    fn encode_falls_back_to_utf8() {
+        let mut res = response(
+            "text/html; charset=utf-16le",
+            r#"<meta http-equiv="Content-Type" content="text/html; charset=UTF-16LE">"#,
+        );
+        encode(&mut res, ::encoding_rs::UTF_16LE);
+        assert_eq!(
+            res.body,
+            r#"<meta http-equiv="Content-Type" content="text/html; charset=UTF-8">"#
+        );
+        assert_eq!(
+            res.headers[header::CONTENT_TYPE],
+            "text/html; charset=UTF-8"
+        );
+    }
+}
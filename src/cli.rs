@@ -0,0 +1,331 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    admin::AdminConfig,
+    charset,
+    client::ClientConfig,
+    config::{Config, ConfigMap, Overrides},
+    cookie::Cookies,
+    cors::Cors,
+    filters::{
+        DefaultRequestFilter, DefaultResponseFilter, DefaultUrlResponseFilter, FilterStep,
+        RequestFilterBuilder, ResponseFilter, ResponseFilterBuilder, ResponseParts,
+    },
+    origin::Origins,
+    tls::TlsConfig,
+};
+
+#[derive(Parser)]
+#[command(author, version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the proxy server (default)
+    Serve(Box<ServeArgs>),
+    /// Validate the configuration and print the resolved config
+    CheckConfig,
+    /// Print the active request and response filters in order
+    ListFilters,
+    /// Apply the response filters to a local file
+    Rewrite(RewriteArgs),
+    /// Apply the response filters step by step and show what each filter did
+    DebugFilters(DebugFiltersArgs),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self::Serve(Default::default())
+    }
+}
+
+impl Command {
+    pub async fn run(self) -> Result<()> {
+        match self {
+            Self::Serve(_) => Ok(()),
+            Self::CheckConfig => check_config(),
+            Self::ListFilters => list_filters(),
+            Self::Rewrite(args) => args.run().await,
+            Self::DebugFilters(args) => args.run().await,
+        }
+    }
+}
+
+macro_rules! define_serve_args {
+    ( $( $( #[doc = $doc:expr] )* $field:ident => $env:expr , )* ) => {
+        #[derive(Args, Default)]
+        pub struct ServeArgs {
+            $(
+                $( #[doc = $doc] )*
+                #[arg(long, value_name = $env)]
+                $field: Option<String>,
+            )*
+        }
+
+        impl ServeArgs {
+            /// Collect the given flags, which override the environment variables on loading the config
+            pub fn overrides(&self) -> Overrides {
+                let mut overrides = Overrides::default();
+                $(
+                    if let Some(value) = &self.$field {
+                        overrides.insert($env, value.clone());
+                    }
+                )*
+                overrides
+            }
+        }
+    };
+}
+
+define_serve_args!(
+    /// Address to listen on
+    bind_addr => "BIND_ADDR",
+    /// Accept h2c with prior knowledge on the plain listener (true or false)
+    bind_h2c => "BIND_H2C",
+    /// Base URL of the proxy
+    base_url => "BASE_URL",
+    /// Base URL of the upstream
+    proxy_base_url => "PROXY_BASE_URL",
+    /// Host of the upstream
+    proxy_host => "PROXY_HOST",
+    /// Other hosts of the upstream, separated by commas
+    proxy_host_aliases => "PROXY_HOST_ALIASES",
+    /// Sibling origins of the upstream to proxy under `_origin/{host}/`, separated by commas
+    proxy_origins => "PROXY_ORIGINS",
+    /// Scheme of the upstream
+    proxy_scheme => "PROXY_SCHEME",
+    /// Register a service worker rewriting the in-scope fetches (true or false)
+    shim_service_worker => "SHIM_SERVICE_WORKER",
+    /// Guard `history.replaceState` in the shim (true or false), only for Notion by default
+    shim_guard_replace_state => "SHIM_GUARD_REPLACE_STATE",
+    /// Cookie mode (rewrite or jar)
+    cookie_mode => "COOKIE_MODE",
+    /// HTTP version to the upstream (auto, http1 or http2)
+    proxy_http_version => "PROXY_HTTP_VERSION",
+    /// CORS mode (translate or local)
+    cors_mode => "CORS_MODE",
+    /// Allowed origins of the local CORS policy, separated by commas
+    cors_allow_origins => "CORS_ALLOW_ORIGINS",
+    /// Address to listen on for the admin endpoints, 127.0.0.1:9090 by default as they are not authenticated
+    admin_bind_addr => "ADMIN_BIND_ADDR",
+    /// Access log format (off, common, combined or json)
+    access_log_format => "ACCESS_LOG_FORMAT",
+    /// Path of the access log file
+    access_log_file => "ACCESS_LOG_FILE",
+    /// Address to listen on for HTTPS
+    tls_bind_addr => "TLS_BIND_ADDR",
+    /// Path of the TLS certificate
+    tls_cert_file => "TLS_CERT_FILE",
+    /// Path of the TLS private key
+    tls_key_file => "TLS_KEY_FILE",
+);
+
+fn check_config() -> Result<()> {
+    let overrides = Overrides::default();
+    let config =
+        Config::try_default(&overrides).map_err(|e| anyhow!("failed to parse config: {e}"))?;
+
+    let client_config = ClientConfig::try_from_env("PROXY_", &overrides)
+        .map_err(|e| anyhow!("failed to parse client config: {e}"))?;
+    let client = client_config.try_build()?;
+    let origins = Origins::try_from_config(&config, &client)?;
+
+    let tls = TlsConfig::try_default(&overrides)
+        .map_err(|e| anyhow!("failed to parse TLS config: {e}"))?;
+    if let Some(tls) = &tls {
+        tls.try_build()?;
+    }
+
+    let cookies = Cookies::try_default(&overrides)
+        .map_err(|e| anyhow!("failed to parse cookie config: {e}"))?;
+    let cors =
+        Cors::try_default(&overrides).map_err(|e| anyhow!("failed to parse CORS config: {e}"))?;
+
+    let admin = AdminConfig::try_default(&overrides)
+        .map_err(|e| anyhow!("failed to parse admin config: {e}"))?;
+
+    DefaultRequestFilter.try_build(&config.to_map())?;
+    DefaultResponseFilter.try_build(&config.to_map())?;
+    DefaultUrlResponseFilter.try_build(&config.to_map())?;
+
+    let resolved = ::serde_json::json!({
+        "admin": admin,
+        "client": client_config,
+        "config": config.to_map(),
+        "cookies": cookies,
+        "cors": cors,
+        "origins": origins,
+        "tls": tls,
+    });
+    println!("{}", ::serde_json::to_string_pretty(&resolved)?);
+    Ok(())
+}
+
+fn list_filters() -> Result<()> {
+    let config_map = load_config_map(Default::default())?;
+
+    let filters = DefaultRequestFilter.try_build(&config_map)?;
+    for (index, filter) in filters.iter().enumerate() {
+        println!("[{index}] request {name}", name = filter.name());
+    }
+
+    // NOTE: the URL filters run before the response filters
+    let filters = DefaultUrlResponseFilter.try_build(&config_map)?;
+    for (index, filter) in filters.iter().enumerate() {
+        println!("[{index}] url {name}", name = filter.name());
+    }
+
+    let filters = DefaultResponseFilter.try_build(&config_map)?;
+    for (index, filter) in filters.iter().enumerate() {
+        println!("[{index}] response {name}", name = filter.name());
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct RewriteArgs {
+    /// Path of the local document
+    file: PathBuf,
+
+    /// Path to write the rewritten document, or stdout if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Additional config map entries, e.g. `--set host=example.com`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    config: Vec<(String, String)>,
+}
+
+impl RewriteArgs {
+    async fn run(self) -> Result<()> {
+        let Self {
+            file,
+            output,
+            config,
+        } = self;
+
+        let body = ::std::fs::read(&file)
+            .map_err(|e| anyhow!("failed to read {path}: {e}", path = file.display()))?;
+
+        let config_map = load_config_map(config)?;
+
+        let filters = DefaultResponseFilter.try_build(&config_map)?;
+        let mut res = ResponseParts::from_html(body);
+        let encoding = charset::decode(&mut res);
+        filters.filter(&config_map, &mut res).await?;
+        charset::encode(&mut res, encoding);
+        match output {
+            Some(output) => ::std::fs::write(&output, &res.body)
+                .map_err(|e| anyhow!("failed to write {path}: {e}", path = output.display())),
+            None => ::std::io::stdout()
+                .write_all(&res.body)
+                .map_err(|e| anyhow!("failed to write to stdout: {e}")),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct DebugFiltersArgs {
+    /// URL of the document to fetch
+    #[arg(long, conflicts_with = "file", required_unless_present = "file")]
+    url: Option<String>,
+
+    /// Path of the local document
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Additional config map entries, e.g. `--set host=example.com`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    config: Vec<(String, String)>,
+
+    /// Print the steps as JSON, including the output of each filter
+    #[arg(long)]
+    json: bool,
+}
+
+impl DebugFiltersArgs {
+    async fn run(self) -> Result<()> {
+        let Self {
+            url,
+            file,
+            config,
+            json,
+        } = self;
+
+        let body = match (url, file) {
+            (Some(url), _) => {
+                let client =
+                    ClientConfig::try_from_env("PROXY_", &Default::default())?.try_build()?;
+                client
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(|e| anyhow!("failed to fetch the document ({url}): {e}"))?
+                    .text()
+                    .await
+                    .map_err(|e| anyhow!("failed to read the document ({url}): {e}"))?
+            }
+            (None, Some(file)) => ::std::fs::read_to_string(&file)
+                .map_err(|e| anyhow!("failed to read {path}: {e}", path = file.display()))?,
+            (None, None) => unreachable!("either --url or --file is required"),
+        };
+
+        let config_map = load_config_map(config)?;
+
+        let filters = DefaultResponseFilter.try_build(&config_map)?;
+        let steps = filters.debug(&config_map, body).await;
+        if json {
+            println!("{}", ::serde_json::to_string_pretty(&steps)?);
+        } else {
+            steps.iter().for_each(print_step);
+        }
+        Ok(())
+    }
+}
+
+/// Load the config map from the environment variables and the given entries,
+/// filling the per-request keys with the sample values
+fn load_config_map(entries: Vec<(String, String)>) -> Result<ConfigMap> {
+    let mut config_map = Config::try_default(&Default::default())
+        .map_err(|e| anyhow!("failed to parse config: {e}"))?
+        .to_map();
+    config_map.extend(entries);
+    config_map.extend_with_samples();
+    Ok(config_map)
+}
+
+fn print_step(step: &FilterStep) {
+    let FilterStep {
+        index,
+        name,
+        matches,
+        changed,
+        diff,
+        error,
+        ..
+    } = step;
+
+    let matches = matches
+        .map(|matches| matches.to_string())
+        .unwrap_or_else(|| "?".into());
+    println!("[{index}] {name}: {matches} match(es), changed: {changed}");
+    if *changed {
+        println!("{diff}");
+    }
+    if let Some(error) = error {
+        println!("error: {error}");
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String)> {
+    s.split_once('=')
+        .map(|(key, value)| (key.into(), value.into()))
+        .ok_or_else(|| anyhow!("invalid KEY=VALUE: {s}"))
+}
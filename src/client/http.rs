@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+use crate::config::Overrides;
+use anyhow::{bail, Error, Result};
+use reqwest::ClientBuilder;
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// Negotiate HTTP/2 with ALPN over TLS, or use HTTP/1.1 otherwise
+    #[default]
+    Auto,
+    /// Use HTTP/1.1 only
+    Http1,
+    /// Use HTTP/2 with prior knowledge, including h2c over cleartext
+    Http2,
+}
+
+impl FromStr for HttpVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "http1" | "http/1.1" => Ok(Self::Http1),
+            "http2" | "h2" | "h2c" => Ok(Self::Http2),
+            _ => bail!("unknown HTTP version: {s}"),
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct HttpConfig {
+    version: HttpVersion,
+}
+
+impl HttpConfig {
+    pub fn try_from_env(prefix: &str, overrides: &Overrides) -> Result<Self> {
+        Ok(Self {
+            version: overrides
+                .infer::<String>(format!("{prefix}HTTP_VERSION"))
+                .map(|version| version.parse())
+                .unwrap_or_else(|_| Ok(Default::default()))?,
+        })
+    }
+
+    /// ALPN protocols to offer on the preconfigured TLS connections
+    pub fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        match self.version {
+            HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+            HttpVersion::Http2 => vec![b"h2".to_vec()],
+        }
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder> {
+        Ok(match self.version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        })
+    }
+}
@@ -0,0 +1,40 @@
+mod http;
+mod proxy;
+mod tls;
+
+use anyhow::{anyhow, Result};
+use reqwest::{Client, ClientBuilder};
+use serde::Serialize;
+
+use crate::config::Overrides;
+
+#[derive(Serialize)]
+pub struct ClientConfig {
+    http: self::http::HttpConfig,
+    proxy: self::proxy::ProxyConfig,
+    tls: self::tls::TlsConfig,
+}
+
+impl ClientConfig {
+    /// Load the upstream client options with the given prefix,
+    /// e.g. `PROXY_TLS_CA_FILE`, `PROXY_EGRESS_HTTPS_PROXY` or `PROXY_HTTP_VERSION` for `PROXY_`.
+    pub fn try_from_env(prefix: &str, overrides: &Overrides) -> Result<Self> {
+        Ok(Self {
+            http: self::http::HttpConfig::try_from_env(prefix, overrides)?,
+            proxy: self::proxy::ProxyConfig::try_from_env(prefix)?,
+            tls: self::tls::TlsConfig::try_from_env(prefix)?,
+        })
+    }
+
+    pub fn try_build(&self) -> Result<Client> {
+        let Self { http, proxy, tls } = self;
+
+        let builder = ClientBuilder::new();
+        let builder = http.apply(builder)?;
+        let builder = proxy.apply(builder)?;
+        let builder = tls.apply(builder, http.alpn_protocols())?;
+        builder
+            .build()
+            .map_err(|e| anyhow!("failed to init reqwest client: {e}"))
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, Result};
+use ark_core::env;
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct ProxyConfig {
+    #[serde(serialize_with = "crate::config::serialize_redacted_url")]
+    http: Option<String>,
+    #[serde(serialize_with = "crate::config::serialize_redacted_url")]
+    https: Option<String>,
+    #[serde(serialize_with = "crate::config::serialize_redacted_url")]
+    all: Option<String>,
+    username: Option<String>,
+    #[serde(serialize_with = "crate::config::serialize_redacted")]
+    password: Option<String>,
+    no_proxy: Option<String>,
+    from_env: bool,
+}
+
+impl ProxyConfig {
+    pub fn try_from_env(prefix: &str) -> Result<Self> {
+        let infer = |key: &str| env::infer::<_, String>(format!("{prefix}EGRESS_{key}")).ok();
+
+        Ok(Self {
+            http: infer("HTTP_PROXY"),
+            https: infer("HTTPS_PROXY"),
+            all: infer("ALL_PROXY"),
+            username: infer("PROXY_USERNAME"),
+            password: infer("PROXY_PASSWORD"),
+            no_proxy: infer("NO_PROXY"),
+            from_env: env::infer(format!("{prefix}EGRESS_PROXY_FROM_ENV")).unwrap_or(true),
+        })
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        let Self {
+            http,
+            https,
+            all,
+            username,
+            password,
+            no_proxy,
+            from_env,
+        } = self;
+
+        // NOTE: explicit proxies always override the ambient `*_PROXY` environment variables
+        if !from_env {
+            builder = builder.no_proxy();
+        }
+
+        let proxies = [
+            (http, Proxy::http as fn(String) -> ::reqwest::Result<Proxy>),
+            (https, Proxy::https),
+            (all, Proxy::all),
+        ];
+        for (url, new_proxy) in proxies {
+            if let Some(url) = url {
+                let mut proxy = new_proxy(url.clone())
+                    .map_err(|e| anyhow!("invalid egress proxy url ({url}): {e}"))?;
+                if let Some(username) = username {
+                    proxy = proxy.basic_auth(username, password.as_deref().unwrap_or_default());
+                }
+                if let Some(no_proxy) = no_proxy {
+                    proxy = proxy.no_proxy(NoProxy::from_string(no_proxy));
+                }
+                builder = builder.proxy(proxy);
+            }
+        }
+        Ok(builder)
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use ark_core::env;
+use reqwest::ClientBuilder;
+use serde::{Serialize, Serializer};
+
+#[derive(Default, Serialize)]
+pub struct TlsConfig {
+    ca_file: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    #[serde(serialize_with = "serialize_sha256")]
+    pinned_sha256: Vec<[u8; 32]>,
+    insecure: bool,
+}
+
+impl TlsConfig {
+    pub fn try_from_env(prefix: &str) -> Result<Self> {
+        let infer_path = |key: &str| env::infer::<_, PathBuf>(format!("{prefix}TLS_{key}")).ok();
+
+        let config = Self {
+            ca_file: infer_path("CA_FILE"),
+            cert_file: infer_path("CERT_FILE"),
+            key_file: infer_path("KEY_FILE"),
+            pinned_sha256: env::infer::<_, String>(format!("{prefix}TLS_PINNED_SHA256"))
+                .map(|pins| {
+                    pins.split(',')
+                        .map(str::trim)
+                        .filter(|pin| !pin.is_empty())
+                        .map(parse_sha256)
+                        .collect::<Result<_>>()
+                })
+                .unwrap_or_else(|_| Ok(Default::default()))?,
+            insecure: env::infer(format!("{prefix}TLS_INSECURE")).unwrap_or_default(),
+        };
+
+        if config.cert_file.is_some() != config.key_file.is_some() {
+            bail!("{prefix}TLS_CERT_FILE and {prefix}TLS_KEY_FILE should be given together");
+        }
+        Ok(config)
+    }
+
+    fn is_default(&self) -> bool {
+        self.ca_file.is_none()
+            && self.cert_file.is_none()
+            && self.pinned_sha256.is_empty()
+            && !self.insecure
+    }
+
+    /// Apply the TLS options, offering the ALPN protocols if the TLS config is built here
+    #[cfg_attr(not(feature = "tls-rustls"), allow(unused_variables))]
+    pub fn apply(
+        &self,
+        builder: ClientBuilder,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> Result<ClientBuilder> {
+        if self.is_default() {
+            return Ok(builder);
+        }
+
+        // NOTE: pinning needs a custom verifier, which reqwest does not expose
+        #[cfg(feature = "tls-rustls")]
+        if !self.pinned_sha256.is_empty() {
+            return self
+                .try_build_rustls(alpn_protocols)
+                .map(|config| builder.use_preconfigured_tls(config));
+        }
+        self.apply_builtin(builder)
+    }
+
+    #[cfg(any(
+        feature = "tls-default",
+        feature = "tls-native",
+        feature = "tls-rustls",
+    ))]
+    fn apply_builtin(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        let Self {
+            ca_file,
+            cert_file,
+            key_file,
+            pinned_sha256,
+            insecure,
+        } = self;
+
+        if !pinned_sha256.is_empty() {
+            bail!("certificate pinning requires the \"tls-rustls\" feature");
+        }
+
+        if let Some(ca_file) = ca_file {
+            for cert in crate::tls::read_certs(ca_file)? {
+                let cert = ::reqwest::Certificate::from_der(&cert.0).map_err(|e| {
+                    anyhow!(
+                        "invalid CA certificate ({path}): {e}",
+                        path = ca_file.display()
+                    )
+                })?;
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (cert_file, key_file) {
+            builder = apply_identity(builder, cert_file, key_file)?;
+        }
+
+        Ok(builder.danger_accept_invalid_certs(*insecure))
+    }
+
+    #[cfg(not(any(
+        feature = "tls-default",
+        feature = "tls-native",
+        feature = "tls-rustls",
+    )))]
+    fn apply_builtin(&self, _builder: ClientBuilder) -> Result<ClientBuilder> {
+        bail!("upstream TLS options require one of the \"tls-*\" features")
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    fn try_build_rustls(&self, alpn_protocols: Vec<Vec<u8>>) -> Result<::rustls::ClientConfig> {
+        use std::sync::Arc;
+
+        use rustls::{client::WebPkiVerifier, OwnedTrustAnchor, RootCertStore};
+
+        let Self {
+            ca_file,
+            cert_file,
+            key_file,
+            pinned_sha256,
+            insecure,
+        } = self;
+
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(::webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        if let Some(ca_file) = ca_file {
+            for cert in crate::tls::read_certs(ca_file)? {
+                roots.add(&cert).map_err(|e| {
+                    anyhow!(
+                        "invalid CA certificate ({path}): {e}",
+                        path = ca_file.display()
+                    )
+                })?;
+            }
+        }
+
+        let verifier = PinnedCertVerifier {
+            inner: if *insecure {
+                None
+            } else {
+                Some(WebPkiVerifier::new(roots, None))
+            },
+            pinned_sha256: pinned_sha256.clone(),
+        };
+
+        let builder = ::rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+        let mut config = match (cert_file, key_file) {
+            (Some(cert_file), Some(key_file)) => builder
+                .with_client_auth_cert(
+                    crate::tls::read_certs(cert_file)?,
+                    crate::tls::read_private_key(key_file)?,
+                )
+                .map_err(|e| {
+                    anyhow!(
+                        "invalid client certificate ({path}): {e}",
+                        path = cert_file.display(),
+                    )
+                })?,
+            _ => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn_protocols;
+        Ok(config)
+    }
+}
+
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+fn apply_identity(
+    builder: ClientBuilder,
+    cert_file: &::std::path::Path,
+    key_file: &::std::path::Path,
+) -> Result<ClientBuilder> {
+    let read = |path: &::std::path::Path| {
+        ::std::fs::read(path)
+            .map_err(|e| anyhow!("failed to read {path}: {e}", path = path.display()))
+    };
+    let cert = read(cert_file)?;
+    let key = read(key_file)?;
+
+    #[cfg(feature = "tls-rustls")]
+    let identity = ::reqwest::Identity::from_pem(&[key, cert].concat());
+    #[cfg(not(feature = "tls-rustls"))]
+    let identity = ::reqwest::Identity::from_pkcs8_pem(&cert, &key);
+
+    identity
+        .map(|identity| builder.identity(identity))
+        .map_err(|e| {
+            anyhow!(
+                "invalid client certificate ({path}): {e}",
+                path = cert_file.display(),
+            )
+        })
+}
+
+#[cfg(all(
+    feature = "tls-default",
+    not(any(feature = "tls-native", feature = "tls-rustls")),
+))]
+fn apply_identity(
+    _builder: ClientBuilder,
+    _cert_file: &::std::path::Path,
+    _key_file: &::std::path::Path,
+) -> Result<ClientBuilder> {
+    bail!("client certificates require the \"tls-native\" or \"tls-rustls\" feature")
+}
+
+#[cfg(feature = "tls-rustls")]
+struct PinnedCertVerifier {
+    inner: Option<::rustls::client::WebPkiVerifier>,
+    pinned_sha256: Vec<[u8; 32]>,
+}
+
+#[cfg(feature = "tls-rustls")]
+impl ::rustls::client::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &::rustls::Certificate,
+        intermediates: &[::rustls::Certificate],
+        server_name: &::rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: ::std::time::SystemTime,
+    ) -> Result<::rustls::client::ServerCertVerified, ::rustls::Error> {
+        use sha2::{Digest, Sha256};
+
+        if let Some(inner) = &self.inner {
+            inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+        }
+
+        let digest: [u8; 32] = Sha256::digest(&end_entity.0).into();
+        if self.pinned_sha256.contains(&digest) {
+            Ok(::rustls::client::ServerCertVerified::assertion())
+        } else {
+            Err(::rustls::Error::General(
+                "upstream certificate does not match any pinned SHA-256 fingerprint".into(),
+            ))
+        }
+    }
+}
+
+fn serialize_sha256<S>(value: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(value.iter().map(|digest| {
+        digest
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }))
+}
+
+/// Parse a SHA-256 fingerprint, e.g. `openssl x509 -noout -fingerprint -sha256`
+fn parse_sha256(pin: &str) -> Result<[u8; 32]> {
+    let error = || anyhow!("invalid SHA-256 fingerprint: {pin}");
+
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(error());
+    }
+
+    let mut digest = [0u8; 32];
+    for (index, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).map_err(|_| error())?;
+    }
+    Ok(digest)
+}
@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, net::SocketAddr, str::FromStr};
 
-use anyhow::Result;
+use actix_web::{http::header, HttpRequest};
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Serialize, Serializer};
 
 macro_rules! define_config {
     (
@@ -21,12 +24,17 @@ macro_rules! define_config {
         }
 
         impl Config {
-            pub fn try_default() -> Result<Self> {
+            /// Names of the fields, which are available as the config map keys
+            pub const KEYS: &'static [&'static str] = &[ $( stringify!($field) , )* ];
+
+            pub fn try_default(overrides: &Overrides) -> Result<Self> {
                 let mut map = ConfigMapInner::default();
                 $(
                     $(
                         define_config!(
-                            @define_field $field: $type = $define_kind ( $define_value, &map )
+                            @define_field $field: $type = $define_kind (
+                                $define_value, &map, overrides
+                            )
                         );
                     )*
                     let $field: $type = $field?;
@@ -51,17 +59,28 @@ macro_rules! define_config {
     };
 
     (
-        @define_field $field:ident : $type:ty = env ( $env:stmt , $map: expr )
+        @define_field $field:ident : $type:ty = env ( $env:stmt , $map: expr , $overrides: expr )
     ) => {
-        let $field: Result<$type> = ::ark_core::env::infer({ $env });
+        let $field: Result<$type> = $overrides.infer({ $env });
     };
     (
-        @define_field $field:ident : $type:ty = default ( $default_fn:stmt , $map: expr )
+        @define_field $field:ident : $type:ty = default (
+            $default_fn:stmt , $map: expr , $overrides: expr
+        )
     ) => {
         let $field: Result<$type> = $field.or_else(|_| Ok({ $default_fn }));
     };
     (
-        @define_field $field:ident : $type:ty = format ( $format:stmt , $map: expr )
+        @define_field $field:ident : $type:ty = default_with (
+            $default_fn:stmt , $map: expr , $overrides: expr
+        )
+    ) => {
+        let $field: Result<$type> = $field.or_else(|_| ({ $default_fn })($map));
+    };
+    (
+        @define_field $field:ident : $type:ty = format (
+            $format:stmt , $map: expr , $overrides: expr
+        )
     ) => {
         let $field: Result<$type> = ::strfmt::strfmt({ $format }, $map)
             .map_err(|error| ::anyhow::anyhow!(
@@ -86,19 +105,66 @@ define_config!(
         #[env = "PROXY_HOST"]
         pub proxy_host: String,
 
+        #[env = "PROXY_HOST_ALIASES", default = "".into()]
+        pub proxy_host_aliases: String,
+
+        #[env = "PROXY_ORIGINS", default = "".into()]
+        pub proxy_origins: String,
+
         #[env = "PROXY_SCHEME", default = "https".into()]
         pub proxy_scheme: String,
 
+        #[env = "SHIM_SERVICE_WORKER", default = "false".into()]
+        pub shim_service_worker: String,
+
+        #[env = "SHIM_GUARD_REPLACE_STATE", default_with = crate::filters::default_shim_guard_replace_state]
+        pub shim_guard_replace_state: String,
+
         /*
             Automatically Formatted
         */
 
         #[format = "{proxy_host}{proxy_base_url}"]
         pub proxy_base_url_with_host: String,
+
+        #[format = &crate::shim::VERSION]
+        pub shim_version: String,
+
+        #[format = &format!("{{base_url}}{path}", path = crate::shim::PATH)]
+        pub shim_url: String,
+
+        #[format = &format!("{{base_url}}{path}", path = crate::shim::SERVICE_WORKER_PATH)]
+        pub shim_service_worker_url: String,
     }
 );
 
-#[derive(Clone, Debug)]
+/// Values given as the command-line flags, overriding the environment variables of the same names
+#[derive(Clone, Debug, Default)]
+pub struct Overrides(HashMap<&'static str, String>);
+
+impl Overrides {
+    pub fn insert(&mut self, key: &'static str, value: String) {
+        self.0.insert(key, value);
+    }
+
+    /// Parse the overridden value of the key, or the environment variable if not given
+    pub fn infer<R>(&self, key: impl AsRef<str>) -> Result<R>
+    where
+        R: FromStr,
+        <R as FromStr>::Err: fmt::Display,
+    {
+        let key = key.as_ref();
+        let value = match self.0.get(key) {
+            Some(value) => value.clone(),
+            None => ::ark_core::env::infer::<_, String>(key)?,
+        };
+        value
+            .parse()
+            .map_err(|e| ::anyhow::anyhow!("failed to parse {key}: {e}"))
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct ConfigMap(ConfigMapInner);
 
 impl ::std::ops::Deref for ConfigMap {
@@ -115,4 +181,344 @@ impl ::std::ops::DerefMut for ConfigMap {
     }
 }
 
+impl<K, V> FromIterator<(K, V)> for ConfigMap
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
 type ConfigMapInner = HashMap<String, String>;
+
+/// Keys which are filled for each request, on top of the config fields:
+///
+/// - `scheme`, `host`: the scheme and host the client requested
+/// - `base_url_with_host`: `{host}{base_url}`
+/// - `upstream_host`: the host of the resolved upstream, either `proxy_host` or a sibling origin
+/// - `path`, `query`: the request path and the raw query string without `?`
+/// - `request_content_type`: the content type of the request body, empty if none
+/// - `client_ip`: the client address, honouring `Forwarded` and `X-Forwarded-For`
+/// - `user`: `X-Forwarded-User` or the basic auth username, empty if anonymous
+/// - `route`: the route of the path, either `upstream` or `_origin/{host}` for a sibling origin
+/// - `csp_nonce`: a random base64 nonce, unique per request
+/// - `status`, `content_type`: the upstream response status code and content type
+///
+/// `client_ip` and `user` trust the forwarded headers as given by the clients,
+/// so they are reliable only behind a reverse proxy which overwrites them.
+///
+/// The values are rendered as is, without HTML nor JavaScript escaping,
+/// so the client-controlled ones should not be inserted into the markup or the scripts.
+pub const REQUEST_KEYS: &[&str] = &[
+    "scheme",
+    "host",
+    "base_url_with_host",
+    "upstream_host",
+    "path",
+    "query",
+    "request_content_type",
+    "client_ip",
+    "user",
+    "route",
+    "csp_nonce",
+    "status",
+    "content_type",
+];
+
+/// Prefixes of the keys which are filled for each request by name:
+///
+/// - `header.<name>`: a request header, with the lowercase name
+/// - `cookie.<name>`: a request cookie
+/// - `response_header.<name>`: an upstream response header, with the lowercase name
+///
+/// The missing headers and cookies are rendered as empty.
+pub const REQUEST_KEY_PREFIXES: &[&str] = &["header.", "cookie.", "response_header."];
+
+pub fn is_request_key(key: &str) -> bool {
+    REQUEST_KEYS.contains(&key)
+        || REQUEST_KEY_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+}
+
+// NOTE: the templates are rendered only by the filters
+#[cfg_attr(
+    not(any(
+        feature = "filter-html",
+        feature = "filter-notion",
+        feature = "filter-origin"
+    )),
+    allow(dead_code)
+)]
+impl ConfigMap {
+    /// Validate the placeholders of the template and render it now
+    /// if it depends only on the static config.
+    pub fn try_prerender(&self, template: &str) -> Result<Option<String>> {
+        let keys = placeholders(template)?;
+        if let Some(key) = keys.iter().find(|key| {
+            !Config::KEYS.contains(key) && !is_request_key(key) && !self.contains_key(**key)
+        }) {
+            bail!("unknown key: {key}");
+        }
+
+        if keys
+            .iter()
+            .all(|key| !is_request_key(key) && self.contains_key(*key))
+        {
+            ::strfmt::strfmt(template, self)
+                .map(Some)
+                .map_err(|e| ::anyhow::anyhow!("failed to render: {e}"))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Render the template for a request, with the missing headers and cookies as empty
+    pub fn render(&self, template: &str) -> Result<String> {
+        let missing = placeholders(template)?
+            .into_iter()
+            .filter(|key| {
+                !self.contains_key(*key)
+                    && REQUEST_KEY_PREFIXES
+                        .iter()
+                        .any(|prefix| key.starts_with(prefix))
+            })
+            .collect::<Vec<_>>();
+
+        let rendered = if missing.is_empty() {
+            ::strfmt::strfmt(template, &self.0)
+        } else {
+            let mut map = self.0.clone();
+            map.extend(missing.into_iter().map(|key| (key.into(), String::new())));
+            ::strfmt::strfmt(template, &map)
+        };
+        rendered.map_err(|e| ::anyhow::anyhow!("failed to render: {e}"))
+    }
+}
+
+impl ConfigMap {
+    /// Fill the missing per-request and per-response keys with the sample values,
+    /// e.g. to apply the filters to a local document
+    pub fn extend_with_samples(&mut self) {
+        let base_url = self.get("base_url").cloned().unwrap_or_else(|| "/".into());
+        let host = self
+            .entry("host".into())
+            .or_insert_with(|| "localhost".into())
+            .clone();
+        let upstream_host = self.get("proxy_host").cloned().unwrap_or_default();
+
+        let samples = [
+            ("scheme", "https".into()),
+            ("base_url_with_host", format!("{host}{base_url}")),
+            ("upstream_host", upstream_host),
+            ("path", base_url),
+            ("query", String::new()),
+            ("request_content_type", String::new()),
+            ("client_ip", "127.0.0.1".into()),
+            ("user", String::new()),
+            ("route", "upstream".into()),
+            ("csp_nonce", STANDARD.encode(::rand::random::<[u8; 16]>())),
+            ("status", "200".into()),
+            ("content_type", "text/html".into()),
+        ];
+        for (key, value) in samples {
+            self.entry(key.into()).or_insert(value);
+        }
+    }
+
+    /// Fill the per-response keys
+    pub fn extend_with_response(&mut self, res: &::reqwest::Response) {
+        self.insert("status".into(), res.status().as_u16().to_string());
+        for (key, value) in res.headers() {
+            let Ok(value) = value.to_str() else { continue };
+            if key == header::CONTENT_TYPE {
+                self.insert("content_type".into(), value.into());
+            }
+            self.insert(format!("response_header.{key}"), value.into());
+        }
+    }
+
+    /// Fill the per-request keys, except the ones derived from the proxy config
+    pub fn extend_with_request(&mut self, req: &HttpRequest) {
+        let connection_info = req.connection_info();
+        let client_ip = connection_info
+            .realip_remote_addr()
+            .map(|addr| match addr.parse::<SocketAddr>() {
+                Ok(addr) => addr.ip().to_string(),
+                Err(_) => addr.to_string(),
+            })
+            .unwrap_or_default();
+
+        let get_header = |key| req.headers().get(key).and_then(|value| value.to_str().ok());
+        let user = get_header("x-forwarded-user")
+            .map(ToString::to_string)
+            .or_else(|| {
+                get_header(header::AUTHORIZATION.as_str())
+                    .and_then(|value| value.strip_prefix("Basic "))
+                    .and_then(|value| STANDARD.decode(value.trim()).ok())
+                    .and_then(|value| String::from_utf8(value).ok())
+                    .and_then(|value| value.split_once(':').map(|(user, _)| user.to_string()))
+            })
+            .unwrap_or_default();
+
+        self.insert("path".into(), req.path().into());
+        self.insert("query".into(), req.query_string().into());
+        self.insert(
+            "request_content_type".into(),
+            get_header(header::CONTENT_TYPE.as_str())
+                .unwrap_or_default()
+                .into(),
+        );
+        self.insert("client_ip".into(), client_ip);
+        self.insert("user".into(), user);
+        self.insert(
+            "csp_nonce".into(),
+            STANDARD.encode(::rand::random::<[u8; 16]>()),
+        );
+
+        for (key, value) in req.headers() {
+            if let Ok(value) = value.to_str() {
+                self.insert(format!("header.{key}"), value.into());
+            }
+        }
+        for value in req.headers().get_all(header::COOKIE) {
+            let Ok(value) = value.to_str() else { continue };
+            for cookie in value.split(';') {
+                if let Some((key, value)) = cookie.trim().split_once('=') {
+                    self.insert(format!("cookie.{key}"), value.into());
+                }
+            }
+        }
+    }
+}
+
+/// Collect the keys of the `{key}` placeholders, skipping the `{{` and `}}` escapes
+fn placeholders(template: &str) -> Result<Vec<&str>> {
+    let mut keys = vec![];
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        let tail = &rest[index..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            rest = &tail[2..];
+        } else if tail.starts_with('}') {
+            bail!("unmatched '}}' in template: {template}");
+        } else {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| ::anyhow::anyhow!("unmatched '{{' in template: {template}"))?;
+            let key = &tail[1..end];
+            // NOTE: strip the format spec, e.g. `{key:>8}`
+            let key = key.split_once(':').map_or(key, |(key, _)| key);
+            keys.push(key);
+            rest = &tail[end + 1..];
+        }
+    }
+    Ok(keys)
+}
+
+const REDACTED: &str = "<redacted>";
+
+pub fn serialize_redacted<S>(
+    value: &Option<String>,
+    serializer: S,
+) -> ::std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    value.as_ref().map(|_| REDACTED).serialize(serializer)
+}
+
+pub fn serialize_redacted_url<S>(
+    value: &Option<String>,
+    serializer: S,
+) -> ::std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    value
+        .as_ref()
+        .map(|value| match ::reqwest::Url::parse(value) {
+            Ok(mut url) if url.password().is_some() => {
+                let _ = url.set_password(Some(REDACTED));
+                url.to_string()
+            }
+            _ => value.clone(),
+        })
+        .serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_skip_escapes() {
+        assert_eq!(placeholders("{{ {base_url} }}").unwrap(), ["base_url"]);
+        assert_eq!(placeholders("{{base_url}}").unwrap(), Vec::<&str>::new());
+        assert_eq!(placeholders("${{1}}{host}").unwrap(), ["host"]);
+        assert_eq!(placeholders("{{{host}}}").unwrap(), ["host"]);
+    }
+
+    #[test]
+    fn placeholders_strip_format_specs() {
+        assert_eq!(
+            placeholders("{status:>8}|{host:<}|{path}").unwrap(),
+            ["status", "host", "path"]
+        );
+    }
+
+    #[test]
+    fn placeholders_reject_unmatched_braces() {
+        assert!(placeholders("{host").is_err());
+        assert!(placeholders("host}").is_err());
+        assert!(placeholders("{host}}").is_err());
+        assert!(placeholders("{{host}").is_err());
+    }
+
+    #[test]
+    fn prerender_static_templates() {
+        let config = ConfigMap::from_iter([("base_url", "/b/")]);
+        assert_eq!(
+            config.try_prerender("{{{base_url}}}").unwrap().as_deref(),
+            Some("{/b/}")
+        );
+        assert_eq!(
+            config.try_prerender("[{base_url:>5}]").unwrap().as_deref(),
+            Some("[  /b/]")
+        );
+    }
+
+    #[test]
+    fn prerender_defers_request_keys() {
+        let config = ConfigMap::from_iter([("base_url", "/b/")]);
+        for template in ["{base_url}{path}", "{header.x-user}", "{cookie.id}"] {
+            assert_eq!(config.try_prerender(template).unwrap(), None, "{template}");
+        }
+    }
+
+    #[test]
+    fn prerender_rejects_unknown_keys() {
+        let config = ConfigMap::from_iter([("base_url", "/b/")]);
+        assert!(config.try_prerender("{unknown}").is_err());
+        assert!(config.try_prerender("{base_url}{path}{unknown}").is_err());
+        assert!(config.try_prerender("{base_url").is_err());
+    }
+
+    #[test]
+    fn render_missing_headers_and_cookies_as_empty() {
+        let config = ConfigMap::from_iter([("path", "/page"), ("header.x-user", "alice")]);
+        assert_eq!(
+            config
+                .render("{path}|{header.x-user}|{header.x-missing}|{cookie.id}")
+                .unwrap(),
+            "/page|alice||"
+        );
+        assert!(config.render("{query}").is_err());
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::HttpRequest;
+use anyhow::{bail, Error, Result};
+use ark_core::env;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use log::debug;
+use reqwest::{
+    cookie::{CookieStore, Jar},
+    header::{self, HeaderValue},
+    Url,
+};
+use serde::Serialize;
+
+use crate::config::Overrides;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieMode {
+    /// Rewrite the upstream cookies to be scoped to the proxy
+    #[default]
+    Rewrite,
+    /// Keep the upstream cookies in the proxy, per client session
+    Jar,
+}
+
+impl FromStr for CookieMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rewrite" => Ok(Self::Rewrite),
+            "jar" => Ok(Self::Jar),
+            _ => bail!("unknown cookie mode: {s}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for SameSite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => bail!("unknown cookie SameSite: {s}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Cookies {
+    mode: CookieMode,
+    same_site: Option<SameSite>,
+    session_name: String,
+    session_ttl_secs: u64,
+    max_sessions: usize,
+    #[serde(skip)]
+    sessions: Mutex<Sessions>,
+}
+
+impl Cookies {
+    pub fn try_default(overrides: &Overrides) -> Result<Self> {
+        let mode = overrides
+            .infer::<String>("COOKIE_MODE")
+            .map(|mode| mode.parse())
+            .unwrap_or_else(|_| Ok(Default::default()))?;
+        let same_site = env::infer::<_, String>("COOKIE_SAME_SITE")
+            .ok()
+            .map(|same_site| same_site.parse())
+            .transpose()?;
+
+        let session_name: String =
+            env::infer("COOKIE_JAR_SESSION_NAME").unwrap_or_else(|_| "__proxy_session".into());
+        if session_name.is_empty()
+            || !session_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            bail!("invalid cookie jar session name: {session_name}");
+        }
+
+        Ok(Self {
+            mode,
+            same_site,
+            session_name,
+            session_ttl_secs: env::infer("COOKIE_JAR_SESSION_TTL_SECS").unwrap_or(86_400),
+            max_sessions: env::infer("COOKIE_JAR_MAX_SESSIONS").unwrap_or(10_000),
+            sessions: Default::default(),
+        })
+    }
+
+    /// Load the cookie jar of the client session, or an empty one if missing or expired
+    ///
+    /// Returns `None` unless the jar mode is enabled.
+    /// A new session is kept only once the upstream sets a cookie.
+    pub fn session(&self, req: &HttpRequest) -> Option<CookieSession> {
+        if self.mode != CookieMode::Jar {
+            return None;
+        }
+
+        let ttl = Duration::from_secs(self.session_ttl_secs);
+        let jar = client_cookies(req)
+            .filter_map(|cookie| cookie.split_once('='))
+            .filter(|(name, _)| *name == self.session_name)
+            .find_map(|(_, id)| {
+                let jar = self
+                    .sessions
+                    .lock()
+                    .unwrap()
+                    .touch(id, Instant::now(), ttl)?;
+                Some((id.to_string(), jar))
+            });
+
+        Some(match jar {
+            Some((id, jar)) => CookieSession { id: Some(id), jar },
+            // NOTE: never accept the session IDs given by the clients
+            None => CookieSession {
+                id: None,
+                jar: Default::default(),
+            },
+        })
+    }
+
+    /// Build the `Cookie` header to the upstream, merging the client cookies and the jar ones
+    pub fn request_header(
+        &self,
+        req: &HttpRequest,
+        session: Option<&CookieSession>,
+        url: &Url,
+    ) -> Option<HeaderValue> {
+        let client = client_cookies(req)
+            .filter(|cookie| {
+                session.is_none()
+                    || cookie.split_once('=').map_or(*cookie, |(name, _)| name) != self.session_name
+            })
+            .map(ToString::to_string);
+        let jar = session
+            .and_then(|session| session.jar.cookies(url))
+            .and_then(|value| value.to_str().ok().map(ToString::to_string));
+
+        let cookies = client.chain(jar).collect::<Vec<_>>();
+        if cookies.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&cookies.join("; ")).ok()
+        }
+    }
+
+    /// Collect the `Set-Cookie` headers to the client,
+    /// either rewritten from the upstream ones or kept in the jar.
+    ///
+    /// The scope maps the upstream paths into the proxy's ones.
+    pub fn response_headers<'a>(
+        &self,
+        session: Option<&CookieSession>,
+        url: &Url,
+        headers: impl Iterator<Item = &'a HeaderValue>,
+        scope: &CookieScope<'_>,
+    ) -> Vec<HeaderValue> {
+        match session {
+            Some(session) => {
+                let mut headers = headers.peekable();
+                let has_cookies = headers.peek().is_some();
+                session.jar.set_cookies(&mut headers, url);
+                if session.id.is_some() || !has_cookies {
+                    return Default::default();
+                }
+
+                let id = URL_SAFE_NO_PAD.encode(::rand::random::<[u8; 32]>());
+                self.sessions.lock().unwrap().insert(
+                    id.clone(),
+                    session.jar.clone(),
+                    Instant::now(),
+                    Duration::from_secs(self.session_ttl_secs),
+                    self.max_sessions,
+                );
+
+                let mut cookie = format!(
+                    "{name}={id}; Path={path}; HttpOnly; SameSite=Lax",
+                    name = self.session_name,
+                    path = scope.base_url,
+                );
+                if scope.is_secure {
+                    cookie.push_str("; Secure");
+                }
+                HeaderValue::from_str(&cookie).ok().into_iter().collect()
+            }
+            None => headers
+                .filter_map(|value| value.to_str().ok())
+                .filter_map(|value| self.rewrite(value, scope))
+                .filter_map(|value| HeaderValue::from_str(&value).ok())
+                .collect(),
+        }
+    }
+
+    /// Rewrite the `Set-Cookie` value to be scoped to the proxy
+    ///
+    /// Returns `None` for the prefixed cookies whose requirements cannot be met through the proxy,
+    /// as the browsers would reject them anyway.
+    fn rewrite(&self, value: &str, scope: &CookieScope<'_>) -> Option<String> {
+        let mut attributes = value.split(';').map(str::trim);
+        let mut cookie = attributes.next().unwrap_or_default().to_string();
+
+        let mut is_secure = false;
+        let mut path = None;
+        let mut same_site = None;
+        for attribute in attributes {
+            let (key, attr_value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            match key.to_ascii_lowercase().as_str() {
+                // NOTE: bind to the proxy host only
+                "domain" => continue,
+                "path" => {
+                    let mapped = scope.map_path(attr_value);
+                    cookie.push_str("; Path=");
+                    cookie.push_str(&mapped);
+                    path = Some(mapped);
+                    continue;
+                }
+                // NOTE: the browsers drop the secure cookies over plain HTTP
+                "secure" => {
+                    is_secure = scope.is_secure;
+                    continue;
+                }
+                "samesite" => {
+                    same_site = attr_value.parse().ok();
+                    continue;
+                }
+                _ => {}
+            }
+            cookie.push_str("; ");
+            cookie.push_str(attribute);
+        }
+
+        let same_site = match self.same_site.or(same_site) {
+            // NOTE: the browsers reject `SameSite=None` without `Secure`
+            Some(SameSite::None) if !scope.is_secure => Some(SameSite::Lax),
+            Some(SameSite::None) => {
+                is_secure = true;
+                Some(SameSite::None)
+            }
+            same_site => same_site,
+        };
+        if is_secure {
+            cookie.push_str("; Secure");
+        }
+        if let Some(same_site) = same_site {
+            cookie.push_str(match same_site {
+                SameSite::Strict => "; SameSite=Strict",
+                SameSite::Lax => "; SameSite=Lax",
+                SameSite::None => "; SameSite=None",
+            });
+        }
+
+        // NOTE: `__Secure-` requires `Secure`, and `__Host-` also requires `Path=/` without `Domain`
+        let is_valid = if cookie.starts_with("__Host-") {
+            is_secure && path.as_deref() == Some("/")
+        } else if cookie.starts_with("__Secure-") {
+            is_secure
+        } else {
+            true
+        };
+        if is_valid {
+            Some(cookie)
+        } else {
+            debug!("dropping the prefixed cookie out of the proxy scope: {value}");
+            None
+        }
+    }
+}
+
+pub struct CookieSession {
+    /// ID of the stored session, or `None` if not stored yet
+    id: Option<String>,
+    jar: Arc<Jar>,
+}
+
+/// Cookie jars of the client sessions, indexed by the last access for the expiry
+#[derive(Default)]
+struct Sessions {
+    jars: HashMap<String, (Arc<Jar>, Instant)>,
+    last_seen: BTreeSet<(Instant, String)>,
+}
+
+impl Sessions {
+    /// Load the jar of the session unless expired, marking it as seen now
+    fn touch(&mut self, id: &str, now: Instant, ttl: Duration) -> Option<Arc<Jar>> {
+        self.expire(now, ttl);
+
+        let (jar, last_seen) = self.jars.get_mut(id)?;
+        let id = self.last_seen.take(&(*last_seen, id.to_string()))?.1;
+        *last_seen = now;
+        self.last_seen.insert((now, id));
+        Some(jar.clone())
+    }
+
+    /// Store a new session, evicting the least recently seen ones over the capacity
+    fn insert(&mut self, id: String, jar: Arc<Jar>, now: Instant, ttl: Duration, max: usize) {
+        self.expire(now, ttl);
+        while !self.jars.is_empty() && self.jars.len() >= max {
+            self.pop_oldest();
+        }
+
+        self.jars.insert(id.clone(), (jar, now));
+        self.last_seen.insert((now, id));
+    }
+
+    fn expire(&mut self, now: Instant, ttl: Duration) {
+        while self
+            .last_seen
+            .first()
+            .is_some_and(|(last_seen, _)| now.duration_since(*last_seen) >= ttl)
+        {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((_, id)) = self.last_seen.pop_first() {
+            self.jars.remove(&id);
+        }
+    }
+}
+
+/// Where the upstream cookies are mapped to in the proxy
+pub struct CookieScope<'a> {
+    /// Whether the client is connected over HTTPS
+    pub is_secure: bool,
+    /// Base path of the proxy, e.g. `BASE_URL`, where the session cookie is scoped to
+    pub base_url: &'a str,
+    /// Base path of the upstream, e.g. `PROXY_BASE_URL`
+    pub upstream_base_url: &'a str,
+    /// Base path of the upstream in the proxy, e.g. `BASE_URL` or `{base_url}_origin/{host}/`
+    pub proxy_base_url: &'a str,
+}
+
+impl CookieScope<'_> {
+    fn map_path(&self, path: &str) -> String {
+        let upstream_base_url = self.upstream_base_url.trim_end_matches('/');
+        match path.strip_prefix(upstream_base_url) {
+            Some(rest) if rest.starts_with('/') => format!(
+                "{base}{rest}",
+                base = self.proxy_base_url.trim_end_matches('/'),
+            ),
+            // NOTE: the paths out of the upstream are narrowed to the upstream
+            _ => self.proxy_base_url.into(),
+        }
+    }
+}
+
+fn client_cookies(req: &HttpRequest) -> impl Iterator<Item = &str> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies(same_site: Option<SameSite>) -> Cookies {
+        Cookies {
+            mode: CookieMode::Rewrite,
+            same_site,
+            session_name: "__proxy_session".into(),
+            session_ttl_secs: 60,
+            max_sessions: 2,
+            sessions: Default::default(),
+        }
+    }
+
+    fn scope(is_secure: bool, proxy_base_url: &str) -> CookieScope<'_> {
+        CookieScope {
+            is_secure,
+            base_url: "/",
+            upstream_base_url: "/app/",
+            proxy_base_url,
+        }
+    }
+
+    #[test]
+    fn map_path_under_the_upstream() {
+        let scope = scope(false, "/proxy/");
+        assert_eq!(scope.map_path("/app/"), "/proxy/");
+        assert_eq!(scope.map_path("/app/docs"), "/proxy/docs");
+
+        let scope = CookieScope {
+            upstream_base_url: "/",
+            ..scope
+        };
+        assert_eq!(scope.map_path("/"), "/proxy/");
+        assert_eq!(scope.map_path("/docs"), "/proxy/docs");
+    }
+
+    #[test]
+    fn map_path_narrows_the_paths_out_of_the_upstream() {
+        let scope = scope(false, "/proxy/");
+        assert_eq!(scope.map_path("/"), "/proxy/");
+        assert_eq!(scope.map_path("/app"), "/proxy/");
+        assert_eq!(scope.map_path("/apps/docs"), "/proxy/");
+        assert_eq!(scope.map_path("/other/docs"), "/proxy/");
+        assert_eq!(scope.map_path(""), "/proxy/");
+    }
+
+    #[test]
+    fn rewrite_strips_the_domain() {
+        assert_eq!(
+            cookies(None)
+                .rewrite(
+                    "id=1; Domain=.example.com; Path=/app/docs; HttpOnly",
+                    &scope(true, "/")
+                )
+                .as_deref(),
+            Some("id=1; Path=/docs; HttpOnly"),
+        );
+    }
+
+    #[test]
+    fn rewrite_narrows_the_path() {
+        assert_eq!(
+            cookies(None)
+                .rewrite("id=1; Path=/", &scope(true, "/proxy/"))
+                .as_deref(),
+            Some("id=1; Path=/proxy/"),
+        );
+    }
+
+    #[test]
+    fn rewrite_downgrades_secure_over_http() {
+        let value = "id=1; Secure; Max-Age=60";
+        assert_eq!(
+            cookies(None).rewrite(value, &scope(false, "/")).as_deref(),
+            Some("id=1; Max-Age=60"),
+        );
+        assert_eq!(
+            cookies(None).rewrite(value, &scope(true, "/")).as_deref(),
+            Some("id=1; Max-Age=60; Secure"),
+        );
+    }
+
+    #[test]
+    fn rewrite_same_site() {
+        let value = "id=1; Secure; SameSite=None";
+        assert_eq!(
+            cookies(None).rewrite(value, &scope(false, "/")).as_deref(),
+            Some("id=1; SameSite=Lax"),
+        );
+        assert_eq!(
+            cookies(None).rewrite(value, &scope(true, "/")).as_deref(),
+            Some("id=1; Secure; SameSite=None"),
+        );
+        assert_eq!(
+            cookies(Some(SameSite::Strict))
+                .rewrite(value, &scope(true, "/"))
+                .as_deref(),
+            Some("id=1; Secure; SameSite=Strict"),
+        );
+        // NOTE: `SameSite=None` requires `Secure`, which is added over HTTPS
+        assert_eq!(
+            cookies(Some(SameSite::None))
+                .rewrite("id=1", &scope(true, "/"))
+                .as_deref(),
+            Some("id=1; Secure; SameSite=None"),
+        );
+    }
+
+    #[test]
+    fn rewrite_host_prefixed_cookies() {
+        let value = "__Host-id=1; Path=/; Secure";
+        let upstream_root = |is_secure, proxy_base_url| CookieScope {
+            upstream_base_url: "/",
+            ..scope(is_secure, proxy_base_url)
+        };
+        assert_eq!(
+            cookies(None)
+                .rewrite(value, &upstream_root(true, "/"))
+                .as_deref(),
+            Some("__Host-id=1; Path=/; Secure"),
+        );
+        assert_eq!(
+            cookies(None).rewrite(value, &upstream_root(false, "/")),
+            None
+        );
+        assert_eq!(
+            cookies(None).rewrite(value, &upstream_root(true, "/proxy/")),
+            None,
+        );
+        assert_eq!(
+            cookies(None).rewrite("__Host-id=1; Secure", &upstream_root(true, "/")),
+            None,
+        );
+    }
+
+    #[test]
+    fn rewrite_secure_prefixed_cookies() {
+        let value = "__Secure-id=1; Path=/app/; Secure";
+        assert_eq!(
+            cookies(None)
+                .rewrite(value, &scope(true, "/proxy/"))
+                .as_deref(),
+            Some("__Secure-id=1; Path=/proxy/; Secure"),
+        );
+        assert_eq!(cookies(None).rewrite(value, &scope(false, "/proxy/")), None);
+    }
+
+    #[test]
+    fn sessions_expire_and_evict_the_least_recently_seen() {
+        let ttl = Duration::from_secs(60);
+        let now = Instant::now();
+        let later = |secs| now + Duration::from_secs(secs);
+        let mut sessions = Sessions::default();
+
+        sessions.insert("a".into(), Default::default(), now, ttl, 2);
+        sessions.insert("b".into(), Default::default(), later(1), ttl, 2);
+        assert!(sessions.touch("a", later(2), ttl).is_some());
+
+        // NOTE: `b` is the least recently seen one
+        sessions.insert("c".into(), Default::default(), later(3), ttl, 2);
+        assert!(sessions.touch("b", later(4), ttl).is_none());
+        assert!(sessions.touch("a", later(5), ttl).is_some());
+        assert!(sessions.touch("c", later(6), ttl).is_some());
+
+        assert!(sessions.touch("a", later(65), ttl).is_none());
+        assert!(sessions.touch("c", later(65), ttl).is_some());
+        assert_eq!(sessions.jars.len(), sessions.last_seen.len());
+        assert!(sessions.touch("c", later(200), ttl).is_none());
+        assert!(sessions.jars.is_empty() && sessions.last_seen.is_empty());
+    }
+}
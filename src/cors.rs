@@ -0,0 +1,218 @@
+use std::str::FromStr;
+
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::{bail, Error, Result};
+use ark_core::env;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+
+use crate::config::Overrides;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CorsMode {
+    /// Forward the preflights and translate the upstream CORS headers to the proxy's origin
+    #[default]
+    Translate,
+    /// Answer the preflights and set the CORS headers from the configured policy
+    Local,
+}
+
+impl FromStr for CorsMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "translate" => Ok(Self::Translate),
+            "local" => Ok(Self::Local),
+            _ => bail!("unknown CORS mode: {s}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Cors {
+    mode: CorsMode,
+    allow_origins: Vec<String>,
+    allow_methods: String,
+    allow_headers: Option<String>,
+    expose_headers: Option<String>,
+    allow_credentials: bool,
+    max_age_secs: Option<u64>,
+}
+
+impl Cors {
+    pub fn try_default(overrides: &Overrides) -> Result<Self> {
+        let mode = overrides
+            .infer::<String>("CORS_MODE")
+            .map(|mode| mode.parse())
+            .unwrap_or_else(|_| Ok(Default::default()))?;
+
+        let allow_origins = overrides
+            .infer::<String>("CORS_ALLOW_ORIGINS")
+            .unwrap_or_else(|_| "*".into())
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let allow_credentials = env::infer("CORS_ALLOW_CREDENTIALS").unwrap_or_default();
+        // NOTE: never reflect an arbitrary origin with credentials
+        if allow_credentials
+            && (allow_origins.is_empty() || allow_origins.iter().any(|origin| origin == "*"))
+        {
+            bail!("CORS_ALLOW_CREDENTIALS requires an explicit list of CORS_ALLOW_ORIGINS");
+        }
+
+        let list = |key| {
+            env::infer::<_, String>(key)
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        };
+        let allow_methods = list("CORS_ALLOW_METHODS")
+            .unwrap_or_else(|| "GET, HEAD, POST, PUT, PATCH, DELETE".into());
+        let allow_headers = list("CORS_ALLOW_HEADERS");
+        let expose_headers = list("CORS_EXPOSE_HEADERS");
+
+        for value in [
+            Some(&allow_methods),
+            allow_headers.as_ref(),
+            expose_headers.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if HeaderValue::from_str(value).is_err() {
+                bail!("invalid CORS header list: {value}");
+            }
+        }
+
+        Ok(Self {
+            mode,
+            allow_origins,
+            allow_methods,
+            allow_headers,
+            expose_headers,
+            allow_credentials,
+            max_age_secs: env::infer("CORS_MAX_AGE_SECS").ok(),
+        })
+    }
+
+    pub fn is_cors_header(key: &HeaderName) -> bool {
+        key.as_str().starts_with("access-control-")
+    }
+
+    /// Answer the preflight request locally, if the policy is configured so
+    pub fn preflight(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        if self.mode != CorsMode::Local || req.method() != ::actix_web::http::Method::OPTIONS {
+            return None;
+        }
+        let origin = req.headers().get(header::ORIGIN)?;
+        let request_headers = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned();
+        req.headers().get(header::ACCESS_CONTROL_REQUEST_METHOD)?;
+
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return Some(HttpResponse::Forbidden().body("CORS origin not allowed"));
+        };
+
+        let mut builder = HttpResponse::NoContent();
+        builder
+            .append_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin))
+            .append_header((
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                self.allow_methods.as_str(),
+            ))
+            .append_header((header::VARY, "Origin"));
+        // NOTE: allow the requested headers if not configured
+        match self.allow_headers.as_deref() {
+            Some(allow_headers) => {
+                builder.append_header((header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers));
+            }
+            None => {
+                if let Some(request_headers) = request_headers {
+                    builder.append_header((header::ACCESS_CONTROL_ALLOW_HEADERS, request_headers));
+                }
+            }
+        }
+        if self.allow_credentials {
+            builder.append_header((header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true"));
+        }
+        if let Some(max_age_secs) = self.max_age_secs {
+            builder.append_header((header::ACCESS_CONTROL_MAX_AGE, max_age_secs));
+        }
+        Some(builder.finish())
+    }
+
+    /// Collect the CORS headers of the response,
+    /// either translated from the upstream ones or given by the policy.
+    ///
+    /// `upstream_origin` is the `Origin` header as sent to the upstream.
+    pub fn response_headers(
+        &self,
+        req: &HttpRequest,
+        upstream_origin: Option<&HeaderValue>,
+        headers: &HeaderMap,
+    ) -> Vec<(HeaderName, HeaderValue)> {
+        let origin = req.headers().get(header::ORIGIN);
+
+        match self.mode {
+            CorsMode::Translate => headers
+                .iter()
+                .filter(|(key, _)| Self::is_cors_header(key))
+                .map(|(key, value)| match (key, origin) {
+                    // NOTE: the upstream allowed its own origin, which is the proxy's one for the client
+                    (&header::ACCESS_CONTROL_ALLOW_ORIGIN, Some(origin))
+                        if Some(value) == upstream_origin =>
+                    {
+                        (key.clone(), origin.clone())
+                    }
+                    _ => (key.clone(), value.clone()),
+                })
+                .collect(),
+            CorsMode::Local => {
+                let Some(allow_origin) = origin.and_then(|origin| self.allow_origin(origin)) else {
+                    return Default::default();
+                };
+
+                let mut headers = vec![
+                    (header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin),
+                    (header::VARY, HeaderValue::from_static("Origin")),
+                ];
+                if self.allow_credentials {
+                    headers.push((
+                        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                        HeaderValue::from_static("true"),
+                    ));
+                }
+                if let Some(expose_headers) = &self.expose_headers {
+                    if let Ok(value) = HeaderValue::from_str(expose_headers) {
+                        headers.push((header::ACCESS_CONTROL_EXPOSE_HEADERS, value));
+                    }
+                }
+                headers
+            }
+        }
+    }
+
+    /// Return the `Access-Control-Allow-Origin` value for the origin, if allowed
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        // NOTE: the wildcard is never combined with credentials, which is rejected on loading
+        if self.allow_origins.iter().any(|allowed| allowed == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+
+        let value = origin.to_str().ok()?;
+        if self
+            .allow_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(value))
+        {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+
+use crate::config::ConfigMap;
+
+use super::super::templates::{self, ResponseFilters};
+
+/// Conditions on the request and response, all of which should be met to apply the filters.
+///
+/// Conditions on the values missing from the config map (e.g. `status` on the CLI) are skipped.
+pub struct Condition<'a> {
+    /// Pattern of the `upstream_host`, e.g. to scope the filters to a site
+    pub upstream_host: Option<Pattern<'a>>,
+    /// Pattern of the request `path`
+    pub path: Option<Pattern<'a>>,
+    /// MIME type of the response, e.g. `text/html` or `application/json`,
+    /// which also routes the responses of that type to the inner filters, even if they are not HTML
+    pub mime: Option<&'a str>,
+    /// Range of the response status code
+    pub status: Option<RangeInclusive<u16>>,
+    pub request_headers: &'a [HeaderPredicate<'a>],
+    pub response_headers: &'a [HeaderPredicate<'a>],
+}
+
+impl Condition<'static> {
+    pub const ANY: Self = Self {
+        upstream_host: None,
+        path: None,
+        mime: None,
+        status: None,
+        request_headers: &[],
+        response_headers: &[],
+    };
+
+    fn try_build(self, name: &str) -> Result<CompiledCondition> {
+        let Self {
+            upstream_host,
+            path,
+            mime,
+            status,
+            request_headers,
+            response_headers,
+        } = self;
+
+        let error = |e: ::regex::Error| anyhow!("invalid condition of the filter ({name}): {e}");
+        let build_headers = |prefix: &str, predicates: &[HeaderPredicate]| {
+            predicates
+                .iter()
+                .map(|predicate| predicate.try_build(prefix).map_err(error))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(CompiledCondition {
+            upstream_host: upstream_host
+                .map(Pattern::try_build)
+                .transpose()
+                .map_err(error)?,
+            path: path.map(Pattern::try_build).transpose().map_err(error)?,
+            mime: match mime {
+                Some(mime) if !mime.contains('/') => {
+                    bail!("invalid MIME type condition of the filter ({name}): {mime}")
+                }
+                mime => mime,
+            },
+            status,
+            headers: build_headers("header.", request_headers)?
+                .into_iter()
+                .chain(build_headers("response_header.", response_headers)?)
+                .collect(),
+        })
+    }
+}
+
+// NOTE: available to the filter templates, which may not use all of them
+#[allow(dead_code)]
+pub enum Pattern<'a> {
+    /// `*` matches within a path segment (or a whole host) and `**` matches across the segments
+    Glob(&'a str),
+    Regex(&'a str),
+}
+
+impl Pattern<'_> {
+    fn try_build(self) -> Result<Regex, ::regex::Error> {
+        match self {
+            Self::Glob(glob) => Regex::new(&glob_to_regex(glob)),
+            Self::Regex(re) => Regex::new(re),
+        }
+    }
+}
+
+/// Predicate on a header, with the lowercase name
+#[allow(dead_code)]
+pub enum HeaderPredicate<'a> {
+    Present(&'a str),
+    Absent(&'a str),
+    Equals(&'a str, &'a str),
+    Matches(&'a str, &'a str),
+}
+
+impl HeaderPredicate<'_> {
+    fn try_build(&self, prefix: &str) -> Result<(String, CompiledPredicate), ::regex::Error> {
+        Ok(match *self {
+            Self::Present(name) => (format!("{prefix}{name}"), CompiledPredicate::Present),
+            Self::Absent(name) => (format!("{prefix}{name}"), CompiledPredicate::Absent),
+            Self::Equals(name, value) => (
+                format!("{prefix}{name}"),
+                CompiledPredicate::Equals(value.into()),
+            ),
+            Self::Matches(name, re) => (
+                format!("{prefix}{name}"),
+                CompiledPredicate::Matches(Regex::new(re)?),
+            ),
+        })
+    }
+}
+
+struct CompiledCondition {
+    upstream_host: Option<Regex>,
+    path: Option<Regex>,
+    mime: Option<&'static str>,
+    status: Option<RangeInclusive<u16>>,
+    headers: Vec<(String, CompiledPredicate)>,
+}
+
+impl CompiledCondition {
+    fn is_match(&self, config: &ConfigMap) -> bool {
+        let Self {
+            upstream_host,
+            path,
+            mime,
+            status,
+            headers,
+        } = self;
+
+        let pattern_matches = |pattern: &Option<Regex>, key| match (pattern, config.get(key)) {
+            (Some(pattern), Some(value)) => pattern.is_match(value),
+            _ => true,
+        };
+        let mime_matches = || match (mime, config.get("content_type")) {
+            (Some(mime), Some(value)) => super::mime_matches(mime, value),
+            _ => true,
+        };
+        let status_matches = || match (status, config.get("status")) {
+            (Some(status), Some(value)) => value
+                .parse()
+                .map(|value| status.contains(&value))
+                .unwrap_or_default(),
+            _ => true,
+        };
+        let headers_match = || {
+            headers
+                .iter()
+                .all(|(key, predicate)| predicate.is_match(config.get(key)))
+        };
+
+        pattern_matches(upstream_host, "upstream_host")
+            && pattern_matches(path, "path")
+            && mime_matches()
+            && status_matches()
+            && headers_match()
+    }
+}
+
+enum CompiledPredicate {
+    Present,
+    Absent,
+    Equals(String),
+    Matches(Regex),
+}
+
+impl CompiledPredicate {
+    fn is_match(&self, value: Option<&String>) -> bool {
+        match (self, value) {
+            (Self::Present, value) => value.is_some(),
+            (Self::Absent, value) => value.is_none(),
+            (Self::Equals(expected), Some(value)) => expected == value,
+            (Self::Matches(re), Some(value)) => re.is_match(value),
+            (_, None) => false,
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&::regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Apply the inner filters only if the condition is met
+pub struct ResponseFilterBuilder<B> {
+    pub name: &'static str,
+    pub condition: Condition<'static>,
+    pub inner: B,
+}
+
+impl<B> templates::ResponseFilterBuilder for ResponseFilterBuilder<B>
+where
+    B: templates::ResponseFilterBuilder<FILTER = ResponseFilters>,
+{
+    type FILTER = ResponseFilters;
+
+    fn try_build(
+        self,
+        config: &ConfigMap,
+    ) -> Result<<Self as templates::ResponseFilterBuilder>::FILTER> {
+        let Self {
+            name,
+            condition,
+            inner,
+        } = self;
+
+        let condition = Arc::new(condition.try_build(name)?);
+        Ok(inner
+            .try_build(config)?
+            .into_iter()
+            .map(|inner| {
+                Box::new(ResponseFilter {
+                    condition: condition.clone(),
+                    inner,
+                }) as Box<dyn templates::ResponseFilter>
+            })
+            .collect())
+    }
+}
+
+pub struct ResponseFilter {
+    condition: Arc<CompiledCondition>,
+    inner: Box<dyn templates::ResponseFilter>,
+}
+
+#[::async_trait::async_trait]
+impl templates::ResponseFilter for ResponseFilter {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        match self.condition.mime {
+            Some(pattern) => super::mime_matches(pattern, mime.essence_str()),
+            None => self.inner.accepts(mime),
+        }
+    }
+
+    fn matches(&self, config: &ConfigMap, body: &[u8]) -> Option<usize> {
+        if self.condition.is_match(config) {
+            self.inner.matches(config, body)
+        } else {
+            Some(0)
+        }
+    }
+
+    async fn filter(&self, config: &ConfigMap, res: &mut templates::ResponseParts) -> Result<()> {
+        if self.condition.is_match(config) {
+            self.inner.filter(config, res).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob_matches(glob: &str, value: &str) -> bool {
+        Regex::new(&glob_to_regex(glob)).unwrap().is_match(value)
+    }
+
+    #[test]
+    fn glob_to_regex_matches_segments() {
+        assert!(glob_matches("/docs/*", "/docs/page"));
+        assert!(!glob_matches("/docs/*", "/docs/a/page"));
+        assert!(glob_matches("/docs/**", "/docs/a/page"));
+        assert!(glob_matches("/docs/**/*.html", "/docs/a/b/page.html"));
+        assert!(glob_matches("/page-?", "/page-1"));
+        assert!(!glob_matches("/page-?", "/page-10"));
+        assert!(!glob_matches("/page-?", "/page-/"));
+    }
+
+    #[test]
+    fn glob_to_regex_escapes_and_anchors() {
+        assert_eq!(glob_to_regex("/a.b"), r"^/a\.b$");
+        assert!(!glob_matches("/a.b", "/axb"));
+        assert!(!glob_matches("/docs", "/docs/page"));
+        assert!(!glob_matches("/docs", "/prefix/docs"));
+        assert!(glob_matches("/(a)+[b]", "/(a)+[b]"));
+    }
+
+    #[test]
+    fn condition_matches_all_fields() {
+        let condition = Condition {
+            upstream_host: Some(Pattern::Glob("*.example.com")),
+            path: Some(Pattern::Regex("^/docs/")),
+            mime: Some("text/*"),
+            status: Some(200..=299),
+            request_headers: &[HeaderPredicate::Absent("x-skip")],
+            response_headers: &[HeaderPredicate::Equals("x-kind", "page")],
+        }
+        .try_build("test")
+        .unwrap();
+
+        let matched = [
+            ("upstream_host", "www.example.com"),
+            ("path", "/docs/page"),
+            ("content_type", "text/html; charset=utf-8"),
+            ("status", "200"),
+            ("response_header.x-kind", "page"),
+        ];
+        assert!(condition.is_match(&ConfigMap::from_iter(matched)));
+
+        for (key, value) in [
+            ("upstream_host", "www.other.com"),
+            ("path", "/blog/page"),
+            ("content_type", "application/json"),
+            ("status", "404"),
+            ("status", "invalid"),
+            ("header.x-skip", "1"),
+            ("response_header.x-kind", "asset"),
+        ] {
+            let mut config = ConfigMap::from_iter(matched);
+            config.insert(key.into(), value.into());
+            assert!(!condition.is_match(&config), "{key}: {value}");
+        }
+    }
+
+    #[test]
+    fn condition_skips_missing_values() {
+        let condition = Condition {
+            upstream_host: Some(Pattern::Glob("example.com")),
+            path: Some(Pattern::Glob("/docs/**")),
+            mime: Some("text/html"),
+            status: Some(200..=299),
+            ..Condition::ANY
+        }
+        .try_build("test")
+        .unwrap();
+
+        assert!(condition.is_match(&ConfigMap::default()));
+    }
+
+    #[test]
+    fn header_predicates_on_missing_headers() {
+        let condition = Condition {
+            request_headers: &[
+                HeaderPredicate::Present("x-present"),
+                HeaderPredicate::Matches("x-matches", "^v[0-9]+$"),
+            ],
+            ..Condition::ANY
+        }
+        .try_build("test")
+        .unwrap();
+
+        assert!(!condition.is_match(&ConfigMap::default()));
+        assert!(!condition.is_match(&ConfigMap::from_iter([("header.x-present", "")])));
+        assert!(condition.is_match(&ConfigMap::from_iter([
+            ("header.x-present", ""),
+            ("header.x-matches", "v2"),
+        ])));
+        assert!(!condition.is_match(&ConfigMap::from_iter([
+            ("header.x-present", ""),
+            ("header.x-matches", "2"),
+        ])));
+    }
+
+    #[test]
+    fn mime_condition_routes_the_responses() {
+        let build = |mime| {
+            let builder = ResponseFilterBuilder {
+                name: "test",
+                condition: Condition {
+                    mime,
+                    ..Condition::ANY
+                },
+                inner: [super::super::regex::ResponseFilterBuilder {
+                    name: "test",
+                    re: "a",
+                    rep: "b",
+                }],
+            };
+            templates::ResponseFilterBuilder::try_build(builder, &ConfigMap::default()).unwrap()
+        };
+        let accepts = |filters: &ResponseFilters, mime: &str| {
+            use templates::ResponseFilter;
+            filters.accepts(&mime.parse().unwrap())
+        };
+
+        let filters = build(None);
+        assert!(accepts(&filters, "text/html; charset=utf-8"));
+        assert!(!accepts(&filters, "application/json"));
+
+        let filters = build(Some("application/json"));
+        assert!(!accepts(&filters, "text/html"));
+        assert!(accepts(&filters, "application/json; charset=utf-8"));
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        let invalid_mime = Condition {
+            mime: Some("html"),
+            ..Condition::ANY
+        };
+        assert!(invalid_mime.try_build("test").is_err());
+
+        let invalid_regex = Condition {
+            path: Some(Pattern::Regex("(")),
+            ..Condition::ANY
+        };
+        assert!(invalid_regex.try_build("test").is_err());
+    }
+}
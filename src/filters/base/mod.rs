@@ -1,2 +1,92 @@
-#[cfg(feature = "regex")]
+#[cfg(feature = "filter-notion")]
+pub mod conditional;
+#[cfg(any(feature = "filter-html", feature = "filter-notion"))]
 pub mod regex;
+#[cfg(feature = "filter-origin")]
+pub mod replace;
+#[cfg(feature = "rhai")]
+pub mod script;
+#[cfg(feature = "filter-html")]
+pub mod upstream_url;
+#[cfg(feature = "wasmtime")]
+pub mod wasm;
+
+/// Template of the filters, such as a regex replacement
+#[cfg(any(
+    feature = "filter-html",
+    feature = "filter-notion",
+    feature = "filter-origin"
+))]
+pub enum Replacement {
+    /// Rendered once on build, as it depends only on the static config
+    Static(String),
+    /// Rendered for each request
+    Template(&'static str),
+}
+
+#[cfg(any(
+    feature = "filter-html",
+    feature = "filter-notion",
+    feature = "filter-origin"
+))]
+impl Replacement {
+    /// Validate the template and prerender it if it depends only on the static config
+    pub fn try_build(
+        config: &crate::config::ConfigMap,
+        template: &'static str,
+    ) -> ::anyhow::Result<Self> {
+        Ok(match config.try_prerender(template)? {
+            Some(rendered) => Self::Static(rendered),
+            None => Self::Template(template),
+        })
+    }
+
+    /// Render the template for a request, with the missing headers and cookies as empty
+    pub fn render(
+        &self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<::std::borrow::Cow<'_, str>> {
+        match self {
+            Self::Static(value) => Ok(::std::borrow::Cow::Borrowed(value)),
+            Self::Template(template) => config.render(template).map(::std::borrow::Cow::Owned),
+        }
+    }
+}
+
+/// Check whether the content type matches the MIME type pattern, e.g. `text/html` or `text/*`
+#[cfg(any(
+    feature = "filter-html",
+    feature = "filter-notion",
+    feature = "filter-origin",
+    feature = "filter-script",
+    feature = "filter-wasm",
+))]
+pub fn mime_matches(pattern: &str, content_type: &str) -> bool {
+    let Some((type_, subtype)) = pattern.split_once('/') else {
+        return false;
+    };
+    match content_type.parse::<::mime::Mime>() {
+        Ok(value) => {
+            (type_ == "*" || type_ == value.type_().as_str())
+                && (subtype == "*" || subtype == value.subtype().as_str())
+        }
+        Err(_) => false,
+    }
+}
+
+/// Parse the comma-separated MIME type patterns, e.g. `text/html,application/json`
+#[cfg(any(feature = "rhai", feature = "wasmtime"))]
+pub fn parse_mime_types(key: &str, value: &str) -> ::anyhow::Result<Vec<String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| {
+            if pattern.contains('/') {
+                Ok(pattern.into())
+            } else {
+                ::anyhow::bail!("invalid MIME type of {key}: {pattern}")
+            }
+        })
+        .collect()
+}
@@ -9,30 +9,92 @@ impl super::super::templates::ResponseFilterBuilder for ResponseFilterBuilder<'s
 
     fn try_build(
         self,
+        config: &crate::config::ConfigMap,
     ) -> ::anyhow::Result<<Self as super::super::templates::ResponseFilterBuilder>::FILTER> {
         let Self { name, re, rep } = self;
 
         Ok(ResponseFilter {
-            regex: ::regex::Regex::new(re).map_err(|e| {
+            name,
+            mime_types: &["text/html"],
+            regex: ::regex::bytes::Regex::new(re).map_err(|e| {
                 ::anyhow::anyhow!("failed to init a regex response filter ({name}): {e}")
             })?,
-            rep,
+            rep: super::Replacement::try_build(config, rep).map_err(|e| {
+                ::anyhow::anyhow!("invalid replacement of the response filter ({name}): {e}")
+            })?,
         })
     }
 }
 
 pub struct ResponseFilter {
-    regex: ::regex::Regex,
-    rep: &'static str,
+    name: &'static str,
+    /// MIME type patterns of the responses to filter, e.g. `text/html` or `*/javascript`
+    mime_types: &'static [&'static str],
+    regex: ::regex::bytes::Regex,
+    rep: super::Replacement,
 }
 
+impl ResponseFilter {
+    /// Wrap an already compiled regex with a replacement rendered by the caller
+    #[cfg(feature = "filter-html")]
+    pub fn with_static(
+        name: &'static str,
+        mime_types: &'static [&'static str],
+        regex: ::regex::bytes::Regex,
+        rep: String,
+    ) -> Self {
+        Self {
+            name,
+            mime_types,
+            regex,
+            rep: super::Replacement::Static(rep),
+        }
+    }
+}
+
+#[::async_trait::async_trait]
 impl super::super::templates::ResponseFilter for ResponseFilter {
-    fn filter(&self, config: &crate::config::ConfigMap, body: String) -> String {
-        let Self { regex, rep } = self;
+    fn name(&self) -> &str {
+        self.name
+    }
 
-        match ::strfmt::strfmt(rep, config) {
-            Ok(rep) => regex.replace_all(&body, rep).into_owned(),
-            Err(_) => body,
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        self.mime_types
+            .iter()
+            .any(|pattern| super::mime_matches(pattern, mime.essence_str()))
+    }
+
+    fn matches(&self, _config: &crate::config::ConfigMap, body: &[u8]) -> Option<usize> {
+        Some(self.regex.find_iter(body).count())
+    }
+
+    async fn filter(
+        &self,
+        config: &crate::config::ConfigMap,
+        res: &mut super::super::templates::ResponseParts,
+    ) -> ::anyhow::Result<()> {
+        let Self {
+            name, regex, rep, ..
+        } = self;
+
+        let rep = rep
+            .render(config)
+            .map_err(|e| ::anyhow::anyhow!("failed to render the response filter ({name}): {e}"))?;
+
+        let mut rewrites = 0;
+        let body = regex.replace_all(&res.body, |captures: &::regex::bytes::Captures| {
+            rewrites += 1;
+            let mut dst = Vec::new();
+            captures.expand(rep.as_bytes(), &mut dst);
+            dst
+        });
+        if let ::std::borrow::Cow::Owned(body) = body {
+            res.body = body.into();
         }
+
+        crate::metrics::FILTER_REWRITES
+            .with_label_values(&[name])
+            .inc_by(rewrites);
+        Ok(())
     }
 }
@@ -0,0 +1,190 @@
+use crate::config::ConfigMap;
+
+use super::{super::templates, Replacement};
+
+/// Encoding of the replaced strings in the body
+pub enum Encoding {
+    Plain,
+    /// `application/x-www-form-urlencoded`
+    Form,
+}
+
+pub struct RequestFilterBuilder<'a> {
+    pub name: &'a str,
+    /// MIME type pattern of the request body, e.g. `application/json` or `text/*`
+    pub content_type: &'a str,
+    pub encoding: Encoding,
+    pub from: &'a str,
+    pub to: &'a str,
+}
+
+impl templates::RequestFilterBuilder for RequestFilterBuilder<'static> {
+    type FILTER = RequestFilter;
+
+    fn try_build(
+        self,
+        config: &ConfigMap,
+    ) -> ::anyhow::Result<<Self as templates::RequestFilterBuilder>::FILTER> {
+        let Self {
+            name,
+            content_type,
+            encoding,
+            from,
+            to,
+        } = self;
+
+        let build = |template| {
+            Replacement::try_build(config, template).map_err(|e| {
+                ::anyhow::anyhow!("invalid template of the request filter ({name}): {e}")
+            })
+        };
+
+        Ok(RequestFilter {
+            name,
+            content_type,
+            encoding,
+            from: build(from)?,
+            to: build(to)?,
+        })
+    }
+}
+
+pub struct RequestFilter {
+    name: &'static str,
+    content_type: &'static str,
+    encoding: Encoding,
+    from: Replacement,
+    to: Replacement,
+}
+
+impl Encoding {
+    fn encode(&self, value: &str) -> String {
+        match self {
+            Self::Plain => value.into(),
+            Self::Form => encode_form(value),
+        }
+    }
+}
+
+impl RequestFilter {
+    fn is_match(&self, config: &ConfigMap) -> bool {
+        config
+            .get("request_content_type")
+            .map(|value| super::mime_matches(self.content_type, value))
+            .unwrap_or_default()
+    }
+
+    fn render(&self, config: &ConfigMap) -> ::anyhow::Result<(String, String)> {
+        let Self {
+            name,
+            encoding,
+            from,
+            to,
+            ..
+        } = self;
+
+        let render = |replacement: &Replacement| {
+            replacement
+                .render(config)
+                .map(|value| encoding.encode(&value))
+                .map_err(|e| ::anyhow::anyhow!("failed to render the request filter ({name}): {e}"))
+        };
+        Ok((render(from)?, render(to)?))
+    }
+}
+
+#[::async_trait::async_trait]
+impl templates::RequestFilter for RequestFilter {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn filter(
+        &self,
+        config: &ConfigMap,
+        req: &mut templates::RequestParts,
+    ) -> ::anyhow::Result<()> {
+        if !self.is_match(config) {
+            return Ok(());
+        }
+        let (from, to) = self.render(config)?;
+        if from.is_empty() {
+            return Ok(());
+        }
+
+        let (body, rewrites) = replace_all(&req.body, from.as_bytes(), to.as_bytes());
+        if rewrites > 0 {
+            req.body = body.into();
+        }
+        crate::metrics::FILTER_REWRITES
+            .with_label_values(&[self.name])
+            .inc_by(rewrites);
+        Ok(())
+    }
+}
+
+/// Replace the non-empty pattern in the bytes, which may not be UTF-8, counting the replacements
+fn replace_all(body: &[u8], from: &[u8], to: &[u8]) -> (Vec<u8>, u64) {
+    let mut replaced = Vec::with_capacity(body.len());
+    let mut rewrites = 0;
+    let mut rest = body;
+    while !rest.is_empty() {
+        if rest.starts_with(from) {
+            replaced.extend_from_slice(to);
+            rest = &rest[from.len()..];
+            rewrites += 1;
+        } else {
+            replaced.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    (replaced, rewrites)
+}
+
+/// Percent-encode the value as a form field, where spaces are written as `+`
+fn encode_form(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                (byte as char).to_string()
+            }
+            b' ' => "+".into(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[::actix_web::test]
+    async fn render_missing_headers_as_empty() {
+        let config = ConfigMap::from_iter([
+            ("request_content_type", "text/plain"),
+            ("base_url", "http://127.0.0.1/"),
+        ]);
+        let filter = templates::RequestFilterBuilder::try_build(
+            RequestFilterBuilder {
+                name: "test",
+                content_type: "text/*",
+                encoding: Encoding::Form,
+                from: "{base_url}",
+                to: "https://{header.x-upstream-host}/a b",
+            },
+            &config,
+        )
+        .unwrap();
+
+        let mut req = templates::RequestParts {
+            method: ::reqwest::Method::POST,
+            headers: Default::default(),
+            body: b"url=http%3A%2F%2F127.0.0.1%2F\xff".as_slice().into(),
+        };
+        templates::RequestFilter::filter(&filter, &config, &mut req)
+            .await
+            .unwrap();
+        assert_eq!(req.body.as_ref(), b"url=https%3A%2F%2F%2Fa+b\xff");
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use ark_core::env;
+use log::{debug, info, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use rhai::{module_resolvers::DummyModuleResolver, Array, Dynamic, Engine, Map, Scope, AST, INT};
+
+use crate::config::ConfigMap;
+
+use super::super::templates::{self, ResponseFilters, ResponseParts};
+
+/// Load the Rhai scripts listed in `FILTER_SCRIPT_FILES` (comma-separated).
+///
+/// The scripts filter the responses of the MIME types in `FILTER_SCRIPT_MIME_TYPES`
+/// (comma-separated patterns, `text/html` by default), e.g. `text/html,application/json`.
+///
+/// Each script runs with the following variables, and may modify all but `config`:
+///
+/// - `body`: the response body as a string
+/// - `status`: the response status code
+/// - `headers`: the response headers as an array of `[name, value]` pairs, with the lowercase names,
+///   keeping the repeated ones such as `set-cookie`; the values out of UTF-8 are hidden,
+///   and dropped if the script changes the headers
+/// - `config`: the config map
+///
+/// A failing script, either throwing, timing out or leaving an invalid variable,
+/// is logged and leaves the response untouched. `print` and `debug` write to the log too.
+pub struct ResponseFilterBuilder;
+
+impl templates::ResponseFilterBuilder for ResponseFilterBuilder {
+    type FILTER = ResponseFilters;
+
+    fn try_build(
+        self,
+        _config: &ConfigMap,
+    ) -> Result<<Self as templates::ResponseFilterBuilder>::FILTER> {
+        let files = env::infer::<_, String>("FILTER_SCRIPT_FILES").unwrap_or_default();
+        let limits = Limits::try_default()?;
+        let mime_types = Arc::new(super::parse_mime_types(
+            "FILTER_SCRIPT_MIME_TYPES",
+            &env::infer::<_, String>("FILTER_SCRIPT_MIME_TYPES")
+                .unwrap_or_else(|_| "text/html".into()),
+        )?);
+
+        files
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(|path| {
+                ResponseFilter::try_load(path.into(), limits, mime_types.clone())
+                    .map(|filter| Box::new(filter) as Box<dyn templates::ResponseFilter>)
+            })
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Limits {
+    timeout: Duration,
+    max_operations: u64,
+    max_string_size: usize,
+    max_array_size: usize,
+    max_map_size: usize,
+}
+
+impl Limits {
+    fn try_default() -> Result<Self> {
+        Ok(Self {
+            timeout: Duration::from_millis(env::infer("FILTER_SCRIPT_TIMEOUT_MS").unwrap_or(100)),
+            max_operations: env::infer("FILTER_SCRIPT_MAX_OPERATIONS").unwrap_or(1_000_000),
+            max_string_size: env::infer("FILTER_SCRIPT_MAX_STRING_SIZE").unwrap_or(16 << 20),
+            max_array_size: env::infer("FILTER_SCRIPT_MAX_ARRAY_SIZE").unwrap_or(65_536),
+            max_map_size: env::infer("FILTER_SCRIPT_MAX_MAP_SIZE").unwrap_or(65_536),
+        })
+    }
+
+    /// Create a sandboxed engine, which aborts the script after the timeout
+    fn engine(&self, name: &str) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(self.max_operations)
+            .set_max_string_size(self.max_string_size)
+            .set_max_array_size(self.max_array_size)
+            .set_max_map_size(self.max_map_size)
+            .set_max_call_levels(32)
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval");
+
+        let deadline = Instant::now() + self.timeout;
+        engine.on_progress(move |_| (Instant::now() > deadline).then(|| "timeout".into()));
+
+        // NOTE: keep the scripts off the server's stdout
+        let print_name = name.to_string();
+        engine.on_print(move |text| info!("script filter ({print_name}): {text}"));
+        let debug_name = name.to_string();
+        engine
+            .on_debug(move |text, _, pos| debug!("script filter ({debug_name}) at {pos}: {text}"));
+        engine
+    }
+}
+
+pub struct ResponseFilter {
+    name: String,
+    ast: Arc<AST>,
+    limits: Limits,
+    mime_types: Arc<Vec<String>>,
+}
+
+impl ResponseFilter {
+    fn try_load(path: PathBuf, limits: Limits, mime_types: Arc<Vec<String>>) -> Result<Self> {
+        let error = |e: &dyn ::std::fmt::Display| {
+            anyhow!(
+                "failed to load a script filter ({path}): {e}",
+                path = path.display()
+            )
+        };
+
+        let name = path
+            .file_stem()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let script = ::std::fs::read_to_string(&path).map_err(|e| error(&e))?;
+        let ast = limits
+            .engine(&name)
+            .compile(script)
+            .map_err(|e| error(&e))?;
+
+        Ok(Self {
+            name,
+            ast: Arc::new(ast),
+            limits,
+            mime_types,
+        })
+    }
+}
+
+#[::async_trait::async_trait]
+impl templates::ResponseFilter for ResponseFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        self.mime_types
+            .iter()
+            .any(|pattern| super::mime_matches(pattern, mime.essence_str()))
+    }
+
+    async fn filter(&self, config: &ConfigMap, res: &mut ResponseParts) -> Result<()> {
+        // NOTE: fail open as the wasm filters do, keeping the response untouched
+        if let Err(e) = self.try_filter(config, res).await {
+            warn!("{e}");
+        }
+        Ok(())
+    }
+}
+
+impl ResponseFilter {
+    async fn try_filter(&self, config: &ConfigMap, res: &mut ResponseParts) -> Result<()> {
+        let Self {
+            name, ast, limits, ..
+        } = self;
+
+        // NOTE: keep the repeated headers, e.g. `set-cookie`, as separate pairs
+        let headers = res
+            .headers
+            .iter()
+            .filter_map(|(key, value)| {
+                Some((key.as_str().to_string(), value.to_str().ok()?.into()))
+            })
+            .collect::<Vec<(String, String)>>();
+
+        let mut scope = Scope::new();
+        scope.push_constant(
+            "config",
+            config
+                .iter()
+                .map(|(key, value)| (key.as_str().into(), Dynamic::from(value.clone())))
+                .collect::<Map>(),
+        );
+        scope.push("status", res.status.as_u16() as INT);
+        scope.push(
+            "headers",
+            headers
+                .iter()
+                .map(|(key, value)| {
+                    Dynamic::from_array(vec![key.clone().into(), value.clone().into()])
+                })
+                .collect::<Array>(),
+        );
+        scope.push("body", String::from_utf8_lossy(&res.body).into_owned());
+
+        // NOTE: the script is CPU-bound, so keep it off the async workers
+        let (engine_name, ast, limits) = (name.clone(), ast.clone(), *limits);
+        let scope = ::actix_web::web::block(move || {
+            limits
+                .engine(&engine_name)
+                .run_ast_with_scope(&mut scope, &ast)
+                .map(|()| scope)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| anyhow!("failed to run the script filter ({name}): {e}"))?
+        .map_err(|e| anyhow!("failed to run the script filter ({name}): {e}"))?;
+
+        let invalid = |key| anyhow!("invalid {key} from the script filter ({name})");
+
+        let status = scope
+            .get_value::<INT>("status")
+            .and_then(|status| u16::try_from(status).ok())
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| invalid("status"))?;
+        let new_headers = scope
+            .get_value::<Array>("headers")
+            .ok_or_else(|| invalid("headers"))?
+            .into_iter()
+            .map(|pair| {
+                let pair = pair
+                    .into_typed_array::<String>()
+                    .map_err(|_| invalid("header"))?;
+                match <[String; 2]>::try_from(pair) {
+                    Ok([key, value]) => Ok((key, value)),
+                    Err(_) => Err(invalid("header")),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let body = scope
+            .get_value::<String>("body")
+            .ok_or_else(|| invalid("body"))?;
+
+        // NOTE: leave the headers untouched unless the script changed them
+        if new_headers != headers {
+            let mut headers = HeaderMap::with_capacity(new_headers.len());
+            for (key, value) in new_headers {
+                headers.append(
+                    HeaderName::from_bytes(key.as_bytes()).map_err(|_| invalid("header name"))?,
+                    HeaderValue::from_str(&value).map_err(|_| invalid("header value"))?,
+                );
+            }
+            res.headers = headers;
+        }
+        res.status = status;
+        res.body = body.into();
+        Ok(())
+    }
+}
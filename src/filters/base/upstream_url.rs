@@ -0,0 +1,73 @@
+/// Rewrite the absolute URLs pointing at the upstream, or at one of its aliases, into the proxy's ones
+///
+/// Only the URLs under `PROXY_BASE_URL` are rewritten, as the others are not reachable through the proxy.
+/// The URLs of the sibling origins are rewritten into `{base_url}_origin/{host}/`.
+pub struct ResponseFilterBuilder<'a> {
+    pub name: &'a str,
+    /// MIME type patterns of the responses to filter, e.g. `text/html` or `*/javascript`
+    pub mime_types: &'a [&'a str],
+}
+
+impl super::super::templates::ResponseFilterBuilder for ResponseFilterBuilder<'static> {
+    type FILTER = super::super::templates::ResponseFilters;
+
+    fn try_build(
+        self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<<Self as super::super::templates::ResponseFilterBuilder>::FILTER> {
+        let Self { name, mime_types } = self;
+
+        let get = |key: &str| {
+            config.get(key).map(String::as_str).ok_or_else(|| {
+                ::anyhow::anyhow!("missing config of the response filter ({name}): {key}")
+            })
+        };
+
+        let build = |hosts: &[&str], path: &str, rep: String| {
+            let hosts = hosts
+                .iter()
+                .map(|host| ::regex::escape(host))
+                .collect::<Vec<_>>()
+                .join("|");
+            let path = ::regex::escape(path.trim_end_matches('/'));
+
+            // NOTE: the URL should end with the base path, e.g. `https://host/base"` or `https://host/base/page`
+            let re = format!(r#"(?:https?:)?//(?i:{hosts}){path}(?:/|([\s"'<>?#)]|$))"#);
+            let rep = format!("{rep}${{1}}", rep = rep.replace('$', "$$"));
+
+            ::regex::bytes::Regex::new(&re)
+                .map(|regex| {
+                    super::regex::ResponseFilter::with_static(name, mime_types, regex, rep)
+                })
+                .map(|filter| Box::new(filter) as Box<dyn super::super::templates::ResponseFilter>)
+                .map_err(|e| {
+                    ::anyhow::anyhow!(
+                        "failed to init an upstream URL response filter ({name}): {e}"
+                    )
+                })
+        };
+
+        let base_url = get("base_url")?;
+        let hosts = ::std::iter::once(get("proxy_host")?)
+            .chain(get("proxy_host_aliases")?.split(','))
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .collect::<Vec<_>>();
+        let upstream = build(&hosts, get("proxy_base_url")?, base_url.into())?;
+
+        let origins = crate::origin::parse(get("proxy_origins")?, get("proxy_scheme")?)?
+            .into_iter()
+            .map(|(_, host)| {
+                build(
+                    &[&host],
+                    "",
+                    format!(
+                        "{base_url}{prefix}{host}/",
+                        prefix = crate::origin::PATH_PREFIX
+                    ),
+                )
+            });
+
+        ::std::iter::once(Ok(upstream)).chain(origins).collect()
+    }
+}
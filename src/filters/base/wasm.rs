@@ -0,0 +1,539 @@
+//! WebAssembly plugin filters, loaded from `FILTER_WASM_FILES` (comma-separated).
+//!
+//! # ABI (v1)
+//!
+//! A plugin is a core module without imports, exporting:
+//!
+//! - `memory`: the linear memory
+//! - `alloc(len: i32) -> i32`: allocate `len` bytes for the input
+//! - `filter_request_v1(ptr: i32, len: i32) -> i64` (optional)
+//! - `filter_response_v1(ptr: i32, len: i32) -> i64` (optional)
+//!
+//! The filter functions receive a JSON document and return the pointer and length
+//! of a JSON document, packed as `ptr << 32 | len`.
+//!
+//! - request: `{"config": {..}, "body": ".."}` -> `{"body"?: "..", "error"?: ".."}`
+//! - response: `{"config": {..}, "status": 200, "headers": [["name", ".."], ..], "body": ".."}`
+//!   -> `{"status"?: 200, "headers"?: [["name", ".."], ..], "body"?: "..", "error"?: ".."}`
+//!
+//! The headers are lists of `[name, value]` pairs, keeping the repeated ones such as `set-cookie`.
+//!
+//! The response filters apply to the MIME types in `FILTER_WASM_MIME_TYPES`
+//! (comma-separated patterns, `text/html` by default), e.g. `text/html,application/json`.
+//!
+//! Each call runs on a fresh instance, limited by fuel and memory size, off the async workers.
+//! A failing plugin, either trapping, running out of fuel, returning an invalid output or an `error`,
+//! is logged and leaves the request or the response untouched.
+
+use std::{borrow::Cow, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use ark_core::env;
+use log::warn;
+use once_cell::sync::OnceCell;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use wasmtime::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::config::ConfigMap;
+
+use super::super::templates::{self, RequestFilters, RequestParts, ResponseFilters, ResponseParts};
+
+const EXPORT_REQUEST: &str = "filter_request_v1";
+const EXPORT_RESPONSE: &str = "filter_response_v1";
+
+/// The plugins shared by the request and the response filters, compiled once
+static PLUGINS: OnceCell<Vec<Arc<Plugin>>> = OnceCell::new();
+
+pub struct RequestFilterBuilder;
+
+impl templates::RequestFilterBuilder for RequestFilterBuilder {
+    type FILTER = RequestFilters;
+
+    fn try_build(
+        self,
+        _config: &ConfigMap,
+    ) -> Result<<Self as templates::RequestFilterBuilder>::FILTER> {
+        Ok(Plugin::try_load_all()?
+            .iter()
+            .filter(|plugin| plugin.has_export(EXPORT_REQUEST))
+            .map(|plugin| {
+                Box::new(RequestFilter(plugin.clone())) as Box<dyn templates::RequestFilter>
+            })
+            .collect())
+    }
+}
+
+pub struct ResponseFilterBuilder;
+
+impl templates::ResponseFilterBuilder for ResponseFilterBuilder {
+    type FILTER = ResponseFilters;
+
+    fn try_build(
+        self,
+        _config: &ConfigMap,
+    ) -> Result<<Self as templates::ResponseFilterBuilder>::FILTER> {
+        Ok(Plugin::try_load_all()?
+            .iter()
+            .filter(|plugin| plugin.has_export(EXPORT_RESPONSE))
+            .map(|plugin| {
+                Box::new(ResponseFilter(plugin.clone())) as Box<dyn templates::ResponseFilter>
+            })
+            .collect())
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Limits {
+    fuel: u64,
+    max_memory: usize,
+}
+
+impl Limits {
+    fn try_default() -> Result<Self> {
+        Ok(Self {
+            fuel: env::infer("FILTER_WASM_FUEL").unwrap_or(1_000_000_000),
+            max_memory: env::infer::<_, usize>("FILTER_WASM_MAX_MEMORY_MB").unwrap_or(64) << 20,
+        })
+    }
+}
+
+struct Plugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    limits: Limits,
+    mime_types: Arc<Vec<String>>,
+}
+
+impl Plugin {
+    fn try_load_all() -> Result<&'static [Arc<Self>]> {
+        PLUGINS
+            .get_or_try_init(Self::try_load_files)
+            .map(Vec::as_slice)
+    }
+
+    fn try_load_files() -> Result<Vec<Arc<Self>>> {
+        let files = env::infer::<_, String>("FILTER_WASM_FILES").unwrap_or_default();
+        let files: Vec<_> = files
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .collect();
+        if files.is_empty() {
+            return Ok(Default::default());
+        }
+
+        let limits = Limits::try_default()?;
+        let mime_types = Arc::new(super::parse_mime_types(
+            "FILTER_WASM_MIME_TYPES",
+            &env::infer::<_, String>("FILTER_WASM_MIME_TYPES")
+                .unwrap_or_else(|_| "text/html".into()),
+        )?);
+        let engine = Self::try_new_engine()?;
+
+        files
+            .into_iter()
+            .map(|path| {
+                Self::try_load(&engine, path.into(), limits, mime_types.clone()).map(Arc::new)
+            })
+            .collect()
+    }
+
+    fn try_new_engine() -> Result<Engine> {
+        let mut config = ::wasmtime::Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).map_err(|e| anyhow!("failed to init wasm engine: {e}"))
+    }
+
+    fn try_load(
+        engine: &Engine,
+        path: PathBuf,
+        limits: Limits,
+        mime_types: Arc<Vec<String>>,
+    ) -> Result<Self> {
+        let module = Module::from_file(engine, &path).map_err(|e| {
+            anyhow!(
+                "failed to load a wasm filter ({path}): {e}",
+                path = path.display()
+            )
+        })?;
+        let name = path
+            .file_stem()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        Self::try_new(engine, name, module, limits, mime_types)
+    }
+
+    fn try_new(
+        engine: &Engine,
+        name: String,
+        module: Module,
+        limits: Limits,
+        mime_types: Arc<Vec<String>>,
+    ) -> Result<Self> {
+        let error =
+            |e: &dyn ::std::fmt::Display| anyhow!("failed to load a wasm filter ({name}): {e}");
+
+        if let Some(import) = module.imports().next() {
+            bail!(error(&format!(
+                "imports are not allowed: {module}::{name}",
+                module = import.module(),
+                name = import.name(),
+            )));
+        }
+        for export in ["memory", "alloc"] {
+            if module.get_export(export).is_none() {
+                bail!(error(&format!("missing export: {export}")));
+            }
+        }
+
+        Ok(Self {
+            name,
+            engine: engine.clone(),
+            module,
+            limits,
+            mime_types,
+        })
+    }
+
+    fn has_export(&self, name: &str) -> bool {
+        self.module.get_export(name).is_some()
+    }
+
+    /// Call the plugin on a blocking thread, as it may run long until the fuel is exhausted
+    async fn call_blocking<I, O>(self: &Arc<Self>, export: &'static str, input: &I) -> Result<O>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        let input = ::serde_json::to_vec(input)?;
+        let plugin = self.clone();
+        let output = ::actix_web::web::block(move || plugin.run(export, &input))
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "failed to run the wasm filter ({name}): {e}",
+                    name = self.name
+                )
+            })??;
+        ::serde_json::from_slice(&output).map_err(|e| {
+            anyhow!(
+                "failed to run the wasm filter ({name}): {e}",
+                name = self.name
+            )
+        })
+    }
+
+    fn run(&self, export: &str, input: &[u8]) -> Result<Vec<u8>> {
+        let Self {
+            name,
+            engine,
+            module,
+            limits,
+            ..
+        } = self;
+        let error =
+            |e: &dyn ::std::fmt::Display| anyhow!("failed to run the wasm filter ({name}): {e}");
+
+        let mut store = Store::new(
+            engine,
+            StoreLimitsBuilder::new()
+                .memory_size(limits.max_memory)
+                .build(),
+        );
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.set_fuel(limits.fuel).map_err(|e| error(&e))?;
+
+        let instance = Linker::new(engine)
+            .instantiate(&mut store, module)
+            .map_err(|e| error(&e))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| error(&"missing memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|e| error(&e))?;
+        let filter = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, export)
+            .map_err(|e| error(&e))?;
+
+        let len = i32::try_from(input.len()).map_err(|e| error(&e))?;
+        let ptr = alloc.call(&mut store, len).map_err(|e| error(&e))?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| error(&e))?;
+
+        let packed = filter.call(&mut store, (ptr, len)).map_err(|e| error(&e))?;
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & u32::MAX as u64) as usize;
+        memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| error(&"output out of bounds"))
+    }
+}
+
+pub struct RequestFilter(Arc<Plugin>);
+
+#[derive(Serialize)]
+struct RequestInput<'a> {
+    config: &'a ConfigMap,
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct RequestOutput {
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[::async_trait::async_trait]
+impl templates::RequestFilter for RequestFilter {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn filter(&self, config: &ConfigMap, req: &mut RequestParts) -> Result<()> {
+        // NOTE: the v1 ABI passes the body as a string, so skip the binary ones
+        let Ok(body) = ::std::str::from_utf8(&req.body) else {
+            return Ok(());
+        };
+        let input = RequestInput { config, body };
+        // NOTE: fail open as the response filters do, keeping the request untouched
+        match self.0.call_blocking(EXPORT_REQUEST, &input).await {
+            Ok(RequestOutput {
+                error: Some(error), ..
+            }) => warn!("wasm filter ({name}) failed: {error}", name = self.0.name),
+            Ok(RequestOutput {
+                body: Some(output), ..
+            }) => req.body = output.into(),
+            Ok(RequestOutput { body: None, .. }) => {}
+            Err(e) => warn!("{e}"),
+        }
+        Ok(())
+    }
+}
+
+pub struct ResponseFilter(Arc<Plugin>);
+
+#[derive(Serialize)]
+struct ResponseInput<'a> {
+    config: &'a ConfigMap,
+    status: u16,
+    headers: Vec<(&'a str, &'a str)>,
+    body: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct ResponseOutput {
+    #[serde(default)]
+    status: Option<u16>,
+    #[serde(default)]
+    headers: Option<Vec<(String, String)>>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[::async_trait::async_trait]
+impl templates::ResponseFilter for ResponseFilter {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        self.0
+            .mime_types
+            .iter()
+            .any(|pattern| super::mime_matches(pattern, mime.essence_str()))
+    }
+
+    async fn filter(&self, config: &ConfigMap, res: &mut ResponseParts) -> Result<()> {
+        let input = ResponseInput {
+            config,
+            status: res.status.as_u16(),
+            headers: res
+                .headers
+                .iter()
+                .filter_map(|(key, value)| Some((key.as_str(), value.to_str().ok()?)))
+                .collect(),
+            body: String::from_utf8_lossy(&res.body),
+        };
+        // NOTE: fail open as the request filters do, keeping the response untouched
+        let output = match self.0.call_blocking(EXPORT_RESPONSE, &input).await {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("{e}");
+                return Ok(());
+            }
+        };
+        if let Err(e) = self.apply(output, res) {
+            warn!("{e}");
+        }
+        Ok(())
+    }
+}
+
+impl ResponseFilter {
+    fn apply(&self, output: ResponseOutput, res: &mut ResponseParts) -> Result<()> {
+        let name = &self.0.name;
+        let ResponseOutput {
+            status,
+            headers,
+            body,
+            error,
+        } = output;
+
+        if let Some(error) = error {
+            bail!("wasm filter ({name}) failed: {error}");
+        }
+        let status = status
+            .map(StatusCode::from_u16)
+            .transpose()
+            .map_err(|e| anyhow!("invalid status from the wasm filter ({name}): {e}"))?;
+        let headers = headers
+            .map(|headers| {
+                let mut map = HeaderMap::with_capacity(headers.len());
+                for (key, value) in headers {
+                    map.append(
+                        HeaderName::from_bytes(key.as_bytes())?,
+                        HeaderValue::from_str(&value)?,
+                    );
+                }
+                Ok::<_, ::anyhow::Error>(map)
+            })
+            .transpose()
+            .map_err(|e| anyhow!("invalid headers from the wasm filter ({name}): {e}"))?;
+
+        if let Some(status) = status {
+            res.status = status;
+        }
+        if let Some(headers) = headers {
+            res.headers = headers;
+        }
+        if let Some(body) = body {
+            res.body = body.into();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::CONTENT_TYPE;
+
+    use super::*;
+
+    const OUTPUT_PTR: u64 = 0x10000;
+
+    fn plugin(wat: &str, fuel: u64) -> Result<ResponseFilter> {
+        let engine = Plugin::try_new_engine()?;
+        let module = Module::new(&engine, wat)?;
+        let limits = Limits {
+            fuel,
+            max_memory: 4 << 20,
+        };
+        let mime_types = Arc::new(vec!["text/html".into()]);
+        Plugin::try_new(&engine, "test".into(), module, limits, mime_types)
+            .map(|plugin| ResponseFilter(Arc::new(plugin)))
+    }
+
+    /// A plugin returning the fixed output, placed above the first page
+    fn output_wat(output: &str) -> String {
+        let packed = OUTPUT_PTR << 32 | output.len() as u64;
+        format!(
+            r#"(module
+                (memory (export "memory") 2)
+                (data (i32.const {OUTPUT_PTR}) "{data}")
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "filter_response_v1") (param i32 i32) (result i64)
+                    i64.const {packed}))"#,
+            data = output.replace('"', "\\\""),
+        )
+    }
+
+    fn response() -> ResponseParts {
+        let mut headers = HeaderMap::default();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        ResponseParts {
+            status: StatusCode::OK,
+            headers,
+            body: "<html></html>".into(),
+        }
+    }
+
+    #[::actix_web::test]
+    async fn unpack_the_output() {
+        let filter = plugin(
+            &output_wat(r#"{"status":201,"body":"filtered"}"#),
+            1_000_000,
+        )
+        .unwrap();
+        let mut res = response();
+        templates::ResponseFilter::filter(&filter, &ConfigMap::default(), &mut res)
+            .await
+            .unwrap();
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.body.as_ref(), b"filtered");
+    }
+
+    #[::actix_web::test]
+    async fn keep_the_response_on_error_output() {
+        let filter = plugin(
+            &output_wat(r#"{"status":500,"body":"","error":"boom"}"#),
+            1_000_000,
+        )
+        .unwrap();
+        let mut res = response();
+        templates::ResponseFilter::filter(&filter, &ConfigMap::default(), &mut res)
+            .await
+            .unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_ref(), b"<html></html>");
+    }
+
+    #[::actix_web::test]
+    async fn fail_open_on_fuel_exhaustion() {
+        let filter = plugin(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "filter_response_v1") (param i32 i32) (result i64)
+                    (loop $forever (br $forever))
+                    i64.const 0))"#,
+            10_000,
+        )
+        .unwrap();
+        let mut res = response();
+        templates::ResponseFilter::filter(&filter, &ConfigMap::default(), &mut res)
+            .await
+            .unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_ref(), b"<html></html>");
+    }
+
+    #[test]
+    fn reject_imports() {
+        let result = plugin(
+            r#"(module
+                (import "env" "log" (func))
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0))"#,
+            1_000_000,
+        );
+        let error = result.err().unwrap().to_string();
+        assert!(
+            error.contains("imports are not allowed: env::log"),
+            "{error}"
+        );
+    }
+}
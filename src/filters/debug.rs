@@ -0,0 +1,74 @@
+use actix_web::web::Bytes;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::config::ConfigMap;
+
+use super::{ResponseFilters, ResponseParts};
+
+#[derive(Serialize)]
+pub struct FilterStep {
+    pub index: usize,
+    pub name: String,
+    pub matches: Option<usize>,
+    pub changed: bool,
+    pub diff: String,
+    pub output: String,
+    pub error: Option<String>,
+}
+
+impl ResponseParts {
+    /// Wrap a local HTML document as a successful response
+    pub fn from_html(body: impl Into<Bytes>) -> Self {
+        let mut headers = HeaderMap::default();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        Self {
+            status: StatusCode::OK,
+            headers,
+            body: body.into(),
+        }
+    }
+}
+
+impl ResponseFilters {
+    /// Apply the filters one by one, recording the output of each step until any error
+    pub async fn debug(&self, config: &ConfigMap, body: String) -> Vec<FilterStep> {
+        let mut steps = Vec::default();
+        let mut res = ResponseParts::from_html(body);
+        for (index, filter) in self.iter().enumerate() {
+            if !res.mime().is_some_and(|mime| filter.accepts(&mime)) {
+                continue;
+            }
+
+            let input = res.body.clone();
+            let matches = filter.matches(config, &input);
+            let error = filter.filter(config, &mut res).await.err();
+
+            let input = String::from_utf8_lossy(&input);
+            let output = String::from_utf8_lossy(&res.body);
+            let diff = TextDiff::from_lines(&input, &output)
+                .unified_diff()
+                .context_radius(1)
+                .header("input", "output")
+                .to_string();
+
+            steps.push(FilterStep {
+                index,
+                name: filter.name().into(),
+                matches,
+                changed: input != output,
+                diff,
+                output: output.into_owned(),
+                error: error.as_ref().map(ToString::to_string),
+            });
+            if error.is_some() {
+                break;
+            }
+        }
+        steps
+    }
+}
@@ -1,6 +1,10 @@
 mod base;
+mod debug;
 mod templates;
 
+pub use self::debug::FilterStep;
 pub use self::templates::{
-    DefaultResponseFilter, ResponseFilter, ResponseFilterBuilder, ResponseFilters,
+    default_shim_guard_replace_state, DefaultRequestFilter, DefaultResponseFilter,
+    DefaultUrlResponseFilter, RequestFilter, RequestFilterBuilder, RequestFilters, RequestParts,
+    ResponseFilter, ResponseFilterBuilder, ResponseFilters, ResponseParts,
 };
@@ -1,49 +1,249 @@
-pub struct DefaultResponseFilter;
+use opentelemetry::context::FutureExt;
+
+#[cfg(feature = "filter-html")]
+mod html;
+#[cfg(feature = "filter-notion")]
+mod notion;
+#[cfg(feature = "filter-origin")]
+mod origin;
+#[cfg(feature = "filter-script")]
+mod script;
+#[cfg(feature = "filter-wasm")]
+mod wasm;
+
+/// Guard `history.replaceState` in the shim by default only if the upstream rewrites the address bar,
+/// i.e. Notion
+#[cfg_attr(not(feature = "filter-notion"), allow(unused_variables))]
+pub fn default_shim_guard_replace_state(
+    config: &::std::collections::HashMap<String, String>,
+) -> ::anyhow::Result<String> {
+    #[cfg(feature = "filter-notion")]
+    if config
+        .get("proxy_host")
+        .is_some_and(|host| self::notion::is_upstream(host))
+    {
+        return Ok(true.to_string());
+    }
+    Ok(false.to_string())
+}
 
-macro_rules! impl_response_filter_builder_for_default_response_filter {
-    ( $( $feature:expr => $mod:ident , )* ) => {
-        $(
-            #[cfg(feature = $feature)]
-            mod $mod;
-        )*
+pub struct DefaultRequestFilter;
+pub struct DefaultResponseFilter;
+pub struct DefaultUrlResponseFilter;
 
-        impl ResponseFilterBuilder for DefaultResponseFilter {
-            type FILTER = ResponseFilters;
+macro_rules! impl_filter_builder_for_default_filter {
+    (
+        $default:ident : $builder:ident => $filters:ident = $const:ident {
+            $( $feature:expr => $mod:ident , )*
+        }
+    ) => {
+        impl $builder for $default {
+            type FILTER = $filters;
 
-            fn try_build(self) -> ::anyhow::Result<<Self as ResponseFilterBuilder>::FILTER> {
+            #[allow(unused_variables)]
+            fn try_build(
+                self,
+                config: &crate::config::ConfigMap,
+            ) -> ::anyhow::Result<<Self as $builder>::FILTER> {
                 // NOTE: ordered!
-                Ok(ResponseFilters(
-                    vec![
-                        $({
-                            #[cfg(feature = $feature)]
-                            Box::new(self::$mod::RESPONSE_FILTER_BUILDER.try_build()?)
-                        }),*
-                    ]
-                ))
+                #[allow(unused_mut)]
+                let mut filters = vec![];
+                $(
+                    #[cfg(feature = $feature)]
+                    filters.extend(self::$mod::$const.try_build(config)?.0);
+                )*
+                Ok($filters(filters))
             }
         }
     };
 }
 
-impl_response_filter_builder_for_default_response_filter!(
-    "filter-html" => html,
-    "filter-notion" => notion,
+impl_filter_builder_for_default_filter!(
+    DefaultRequestFilter: RequestFilterBuilder => RequestFilters = REQUEST_FILTER_BUILDER {
+        "filter-origin" => origin,
+        "filter-wasm" => wasm,
+    }
 );
 
+impl_filter_builder_for_default_filter!(
+    DefaultResponseFilter: ResponseFilterBuilder => ResponseFilters = RESPONSE_FILTER_BUILDER {
+        "filter-html" => html,
+        "filter-notion" => notion,
+        "filter-script" => script,
+        "filter-wasm" => wasm,
+    }
+);
+
+impl_filter_builder_for_default_filter!(
+    DefaultUrlResponseFilter: ResponseFilterBuilder => ResponseFilters = URL_RESPONSE_FILTER_BUILDER {
+        "filter-html" => html,
+    }
+);
+
+pub struct RequestFilters(Vec<Box<dyn RequestFilter>>);
+
+impl RequestFilters {
+    pub fn iter(&self) -> impl Iterator<Item = &dyn RequestFilter> {
+        self.0.iter().map(AsRef::as_ref)
+    }
+}
+
+impl FromIterator<Box<dyn RequestFilter>> for RequestFilters {
+    fn from_iter<T: IntoIterator<Item = Box<dyn RequestFilter>>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+#[::async_trait::async_trait]
+impl RequestFilter for RequestFilters {
+    fn name(&self) -> &str {
+        "filters"
+    }
+
+    async fn filter(
+        &self,
+        config: &crate::config::ConfigMap,
+        req: &mut RequestParts,
+    ) -> ::anyhow::Result<()> {
+        for filter in &self.0 {
+            let _timer = crate::metrics::FILTER_DURATION
+                .with_label_values(&[filter.name()])
+                .start_timer();
+            let cx = crate::telemetry::start(
+                &::opentelemetry::Context::current(),
+                format!("request filter {name}", name = filter.name()),
+            );
+            let result = filter.filter(config, req).with_context(cx.clone()).await;
+            if let Err(e) = &result {
+                crate::telemetry::set_error(&cx, e);
+            }
+            crate::telemetry::end(&cx);
+            result?;
+        }
+        Ok(())
+    }
+}
+
+pub trait RequestFilterBuilder {
+    type FILTER: 'static + RequestFilter;
+
+    /// Build the filter, validating its templates against the static config
+    fn try_build(
+        self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<<Self as RequestFilterBuilder>::FILTER>;
+}
+
+impl<T, const N: usize> RequestFilterBuilder for [T; N]
+where
+    T: RequestFilterBuilder,
+{
+    type FILTER = RequestFilters;
+
+    fn try_build(
+        self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<<Self as RequestFilterBuilder>::FILTER> {
+        self.into_iter()
+            .map(|builder| {
+                RequestFilterBuilder::try_build(builder, config)
+                    .map(|filter| Box::new(filter) as Box<dyn RequestFilter>)
+            })
+            .collect::<::anyhow::Result<_>>()
+            .map(RequestFilters)
+    }
+}
+
+/// Client request with a body, which the filters may modify before being sent to the upstream
+pub struct RequestParts {
+    pub method: ::reqwest::Method,
+    pub headers: ::reqwest::header::HeaderMap,
+    pub body: ::actix_web::web::Bytes,
+}
+
+#[::async_trait::async_trait]
+pub trait RequestFilter
+where
+    Self: Send + Sync,
+{
+    fn name(&self) -> &str;
+
+    async fn filter(
+        &self,
+        config: &crate::config::ConfigMap,
+        req: &mut RequestParts,
+    ) -> ::anyhow::Result<()>;
+}
+
 pub struct ResponseFilters(Vec<Box<dyn ResponseFilter>>);
 
+impl ResponseFilters {
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ResponseFilter> {
+        self.0.iter().map(AsRef::as_ref)
+    }
+}
+
+impl FromIterator<Box<dyn ResponseFilter>> for ResponseFilters {
+    fn from_iter<T: IntoIterator<Item = Box<dyn ResponseFilter>>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for ResponseFilters {
+    type Item = Box<dyn ResponseFilter>;
+    type IntoIter = ::std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[::async_trait::async_trait]
 impl ResponseFilter for ResponseFilters {
-    fn filter(&self, config: &crate::config::ConfigMap, body: String) -> String {
-        self.0
-            .iter()
-            .fold(body, |body, filter| filter.filter(config, body))
+    fn name(&self) -> &str {
+        "filters"
+    }
+
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        self.0.iter().any(|filter| filter.accepts(mime))
+    }
+
+    /// Apply the filters accepting the content type of the response, which a filter may change
+    async fn filter(
+        &self,
+        config: &crate::config::ConfigMap,
+        res: &mut ResponseParts,
+    ) -> ::anyhow::Result<()> {
+        for filter in &self.0 {
+            if !res.mime().is_some_and(|mime| filter.accepts(&mime)) {
+                continue;
+            }
+            let _timer = crate::metrics::FILTER_DURATION
+                .with_label_values(&[filter.name()])
+                .start_timer();
+            let cx = crate::telemetry::start(
+                &::opentelemetry::Context::current(),
+                format!("filter {name}", name = filter.name()),
+            );
+            let result = filter.filter(config, res).with_context(cx.clone()).await;
+            if let Err(e) = &result {
+                crate::telemetry::set_error(&cx, e);
+            }
+            crate::telemetry::end(&cx);
+            result?;
+        }
+        Ok(())
     }
 }
 
 pub trait ResponseFilterBuilder {
     type FILTER: 'static + ResponseFilter;
 
-    fn try_build(self) -> ::anyhow::Result<<Self as ResponseFilterBuilder>::FILTER>;
+    /// Build the filter, validating its templates against the static config
+    fn try_build(
+        self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<<Self as ResponseFilterBuilder>::FILTER>;
 }
 
 impl<T, const N: usize> ResponseFilterBuilder for [T; N]
@@ -52,10 +252,13 @@ where
 {
     type FILTER = ResponseFilters;
 
-    fn try_build(self) -> ::anyhow::Result<<Self as ResponseFilterBuilder>::FILTER> {
+    fn try_build(
+        self,
+        config: &crate::config::ConfigMap,
+    ) -> ::anyhow::Result<<Self as ResponseFilterBuilder>::FILTER> {
         self.into_iter()
             .map(|builder| {
-                ResponseFilterBuilder::try_build(builder)
+                ResponseFilterBuilder::try_build(builder, config)
                     .map(|filter| Box::new(filter) as Box<dyn ResponseFilter>)
             })
             .collect::<::anyhow::Result<_>>()
@@ -63,9 +266,43 @@ where
     }
 }
 
+/// Upstream response, which the filters may modify
+pub struct ResponseParts {
+    pub status: ::reqwest::StatusCode,
+    pub headers: ::reqwest::header::HeaderMap,
+    pub body: ::actix_web::web::Bytes,
+}
+
+impl ResponseParts {
+    /// Parse the content type of the response, if any
+    pub fn mime(&self) -> Option<::mime::Mime> {
+        self.headers
+            .get(::reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    }
+}
+
+#[::async_trait::async_trait]
 pub trait ResponseFilter
 where
     Self: Send + Sync,
 {
-    fn filter(&self, config: &crate::config::ConfigMap, body: String) -> String;
+    fn name(&self) -> &str;
+
+    /// Check whether the filter applies to the responses of the MIME type, only HTML by default
+    fn accepts(&self, mime: &::mime::Mime) -> bool {
+        mime.subtype() == ::mime::HTML
+    }
+
+    /// Count the matches of the given body, if the filter supports it
+    fn matches(&self, _config: &crate::config::ConfigMap, _body: &[u8]) -> Option<usize> {
+        None
+    }
+
+    async fn filter(
+        &self,
+        config: &crate::config::ConfigMap,
+        res: &mut ResponseParts,
+    ) -> ::anyhow::Result<()>;
 }
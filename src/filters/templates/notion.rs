@@ -2,20 +2,36 @@
 
 const NAME: &str = "notion";
 
-pub const RESPONSE_FILTER_BUILDER: [super::super::base::regex::ResponseFilterBuilder; 3] = [
-    super::super::base::regex::ResponseFilterBuilder {
-        name: NAME,
-        re: r#"(domainBaseUrl:")https?://[\.\/a-z]+""#,
-        rep: r#"${{1}}{scheme}://{base_url_with_host}""#,
-    },
-    super::super::base::regex::ResponseFilterBuilder {
-        name: NAME,
-        re: r#"(publicDomainName:")[\.a-z]+""#,
-        rep: r#"${{1}}{host}""#,
-    },
-    super::super::base::regex::ResponseFilterBuilder {
-        name: NAME,
-        re: r#"<body[ \.\_\-\=A-Za-z0-9'"]*>"#,
-        rep: r#"${{0}}<script>(function() {{ var proxied = window.history.replaceState; window.history.replaceState = function(state) {{ if (arguments[1] !== 'bypass') return; return proxied.apply(window.history, arguments); }}; }})();</script>"#,
+/// Pattern of the Notion hosts
+const UPSTREAM_HOST: &str = r"^(?:www\.)?notion\.so$|\.notion\.site$";
+
+/// Check whether the host is a Notion one, whose pages need the shim to guard `history.replaceState`
+pub fn is_upstream(host: &str) -> bool {
+    ::regex::Regex::new(UPSTREAM_HOST).is_ok_and(|re| re.is_match(host))
+}
+
+pub const RESPONSE_FILTER_BUILDER: super::super::base::conditional::ResponseFilterBuilder<
+    [super::super::base::regex::ResponseFilterBuilder; 2],
+> = super::super::base::conditional::ResponseFilterBuilder {
+    name: NAME,
+    // NOTE: skip the other sites and the error pages
+    condition: super::super::base::conditional::Condition {
+        upstream_host: Some(super::super::base::conditional::Pattern::Regex(
+            UPSTREAM_HOST,
+        )),
+        status: Some(200..=299),
+        ..super::super::base::conditional::Condition::ANY
     },
-];
+    inner: [
+        super::super::base::regex::ResponseFilterBuilder {
+            name: NAME,
+            re: r#"(domainBaseUrl:")https?://[\.\/a-z]+""#,
+            rep: r#"${{1}}{scheme}://{base_url_with_host}""#,
+        },
+        super::super::base::regex::ResponseFilterBuilder {
+            name: NAME,
+            re: r#"(publicDomainName:")[\.a-z]+""#,
+            rep: r#"${{1}}{host}""#,
+        },
+    ],
+};
@@ -0,0 +1,26 @@
+const NAME: &str = "origin";
+
+/// Rewrite the proxy's own origin, which the apps echo back, into the upstream's one
+pub const REQUEST_FILTER_BUILDER: [super::super::base::replace::RequestFilterBuilder; 3] = [
+    super::super::base::replace::RequestFilterBuilder {
+        name: NAME,
+        content_type: "application/json",
+        encoding: super::super::base::replace::Encoding::Plain,
+        from: "{scheme}://{base_url_with_host}",
+        to: "{proxy_scheme}://{proxy_base_url_with_host}",
+    },
+    super::super::base::replace::RequestFilterBuilder {
+        name: NAME,
+        content_type: "application/x-www-form-urlencoded",
+        encoding: super::super::base::replace::Encoding::Form,
+        from: "{scheme}://{base_url_with_host}",
+        to: "{proxy_scheme}://{proxy_base_url_with_host}",
+    },
+    super::super::base::replace::RequestFilterBuilder {
+        name: NAME,
+        content_type: "text/*",
+        encoding: super::super::base::replace::Encoding::Plain,
+        from: "{scheme}://{base_url_with_host}",
+        to: "{proxy_scheme}://{proxy_base_url_with_host}",
+    },
+];
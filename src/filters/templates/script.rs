@@ -0,0 +1,2 @@
+pub const RESPONSE_FILTER_BUILDER: super::super::base::script::ResponseFilterBuilder =
+    super::super::base::script::ResponseFilterBuilder;
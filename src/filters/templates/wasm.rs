@@ -0,0 +1,5 @@
+pub const REQUEST_FILTER_BUILDER: super::super::base::wasm::RequestFilterBuilder =
+    super::super::base::wasm::RequestFilterBuilder;
+
+pub const RESPONSE_FILTER_BUILDER: super::super::base::wasm::ResponseFilterBuilder =
+    super::super::base::wasm::ResponseFilterBuilder;
@@ -1,33 +1,84 @@
+mod access_log;
+mod admin;
+mod charset;
+mod cli;
+mod client;
 mod config;
+mod cookie;
+mod cors;
 mod filters;
+mod metrics;
+mod origin;
+mod shim;
+mod telemetry;
+mod tls;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc, time::Instant};
 
 use actix_web::{
-    web::{self, BytesMut},
+    web::{self, Bytes, BytesMut},
     App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder,
 };
 use anyhow::{anyhow, Result};
-use ark_core::{env, logger};
-use filters::ResponseFilters;
-use futures::StreamExt;
-use log::{info, warn};
+use ark_core::logger;
+use clap::Parser;
+use filters::{RequestFilters, ResponseFilters};
+use futures::{stream::LocalBoxStream, Stream, StreamExt};
+use log::warn;
+use opentelemetry::context::FutureExt;
 use reqwest::{
     header::{self, HeaderName, HeaderValue},
     Client, Method,
 };
 
 use crate::{
-    config::{Config, ConfigMap},
-    filters::{DefaultResponseFilter, ResponseFilter, ResponseFilterBuilder},
+    access_log::{AccessLog, UpstreamInfo},
+    admin::AdminConfig,
+    cli::{Cli, Command},
+    client::ClientConfig,
+    config::{Config, ConfigMap, Overrides},
+    cookie::{CookieScope, Cookies},
+    cors::Cors,
+    filters::{
+        DefaultRequestFilter, DefaultResponseFilter, DefaultUrlResponseFilter, RequestFilter,
+        RequestFilterBuilder, RequestParts, ResponseFilter, ResponseFilterBuilder, ResponseParts,
+    },
+    origin::Origins,
+    tls::TlsConfig,
 };
 
 async fn resolve(
     context: web::Data<Context>,
     req: HttpRequest,
     method: Method,
-    mut payload: web::Payload,
+    payload: web::Payload,
 ) -> impl Responder {
+    let in_flight = metrics::InFlightGuard::enter();
+    let started = Instant::now();
+    let access_log = context.access_log.clone();
+    let path = req.path();
+    let route = context.origins.route(
+        path.strip_prefix(context.config.base_url.as_str())
+            .unwrap_or(path),
+    );
+    let cx = telemetry::start_server(&req, &route);
+
+    let res = try_resolve(context, &req, method.clone(), payload, &route, &cx).await;
+    telemetry::set_status_code(&cx, res.status().as_u16());
+    telemetry::end(&cx);
+    metrics::observe_request(method.as_str(), res.status().as_str(), &route);
+    let res = metrics::wrap_in_flight(in_flight, res);
+    access_log.wrap(&req, res, started)
+}
+
+async fn try_resolve(
+    context: web::Data<Context>,
+    req: &HttpRequest,
+    method: Method,
+    mut payload: web::Payload,
+    route: &str,
+    cx: &::opentelemetry::Context,
+) -> HttpResponse {
     fn patch_host(
         key: &HeaderName,
         value: &HeaderValue,
@@ -43,21 +94,78 @@ async fn resolve(
             .and_then(|value| HeaderValue::from_str(&value).map_err(|_| error()))
     }
 
+    /// Check whether the header is connection-specific, which is forbidden over HTTP/2
+    fn is_hop_by_hop(key: &HeaderName) -> bool {
+        matches!(
+            *key,
+            header::CONNECTION
+                | header::TE
+                | header::TRAILER
+                | header::TRANSFER_ENCODING
+                | header::UPGRADE
+        ) || matches!(key.as_str(), "keep-alive" | "proxy-connection")
+    }
+
+    /// Replace the hosts in a single pass, so that the replaced ones are not replaced again
+    fn patch_hosts(
+        key: &HeaderName,
+        value: &HeaderValue,
+        hosts: &[(String, String)],
+    ) -> Result<HeaderValue> {
+        let error = || anyhow!("invalid header: {key}");
+
+        let mut rest = value.to_str().map_err(|_| error())?;
+        let mut patched = String::with_capacity(rest.len());
+        'patch: while !rest.is_empty() {
+            for (src, target) in hosts {
+                if let Some(tail) = rest.strip_prefix(src.as_str()) {
+                    patched.push_str(target);
+                    rest = tail;
+                    continue 'patch;
+                }
+            }
+            let mut chars = rest.chars();
+            patched.extend(chars.next());
+            rest = chars.as_str();
+        }
+        HeaderValue::from_str(&patched).map_err(|_| error())
+    }
+
     // load proxy context
     let Context {
+        access_log: _,
         client,
+        client_config: _,
         config:
             Config {
                 base_url,
                 proxy_base_url,
                 proxy_base_url_with_host,
                 proxy_host,
+                proxy_host_aliases: _,
+                proxy_origins: _,
                 proxy_scheme,
+                shim_service_worker: _,
+                shim_guard_replace_state: _,
+                shim_version: _,
+                shim_url: _,
+                shim_service_worker_url: _,
             },
         config_map,
+        cookies,
+        cors,
         filters,
+        origins,
+        request_filters,
+        url_filters,
+        filters_max_body_size,
     } = &**context;
 
+    // answer the preflight locally if configured
+    if let Some(res) = cors.preflight(req) {
+        return res;
+    }
+
     // parse path
     let path = req.path();
     let path = if path.starts_with(base_url) {
@@ -76,7 +184,7 @@ async fn resolve(
         value
     }
 
-    let _scheme = get_param(&mut config_map, "scheme", || {
+    let scheme = get_param(&mut config_map, "scheme", || {
         req.connection_info().scheme().to_string()
     });
     let host = get_param(&mut config_map, "host", || {
@@ -85,42 +193,94 @@ async fn resolve(
     let base_url_with_host = get_param(&mut config_map, "base_url_with_host", || {
         format!("{host}{base_url}")
     });
-    let peer_addr = req
-        .peer_addr()
-        .map(|addr| addr.to_string())
-        .unwrap_or_else(|| "unknown".into());
+    config_map.extend_with_request(req);
+    config_map.insert("route".into(), route.into());
     let query = match req.query_string() {
         "" => Default::default(),
         query => format!("?{query}"),
     };
 
+    // resolve the upstream, which is either the proxied host or one of its sibling origins
+    let (client, upstream_scheme, upstream_host, upstream_base_url, local_base_url, path) =
+        match origins.split_path(path) {
+            Some(Ok((origin, path))) => (
+                &origin.client,
+                origin.scheme.as_str(),
+                origin.host.as_str(),
+                "/",
+                format!(
+                    "{base_url}{prefix}{host}/",
+                    prefix = origin::PATH_PREFIX,
+                    host = origin.host,
+                ),
+                path,
+            ),
+            Some(Err(e)) => return HttpResponse::NotFound().body(e.to_string()),
+            None => (
+                client,
+                proxy_scheme.as_str(),
+                proxy_host.as_str(),
+                proxy_base_url.as_str(),
+                base_url.clone(),
+                path,
+            ),
+        };
+
+    config_map.insert("upstream_host".into(), upstream_host.into());
+
     // get proxy path
-    let proxy_path = format!("{proxy_base_url}{path}{query}");
-    let proxy_url = format!("{proxy_scheme}://{proxy_host}{proxy_path}");
+    let proxy_path = format!("{upstream_base_url}{path}{query}");
+    let proxy_url = match format!("{upstream_scheme}://{upstream_host}{proxy_path}")
+        .parse::<::reqwest::Url>()
+    {
+        Ok(url) => url,
+        Err(e) => return HttpResponse::Forbidden().body(format!("invalid upstream url: {e}")),
+    };
+
+    // load the cookie jar of the client session, if enabled
+    let session = cookies.session(req);
 
-    // define a request
-    let mut builder = client.request(method.clone(), &proxy_url);
+    // patch the request headers
+    let mut headers = header::HeaderMap::default();
+    let span = telemetry::start(cx, "patch request headers");
+    let mut upstream_origin = None;
     for (key, value) in req.headers() {
         match match *key {
             #[cfg(not(feature = "compression"))]
             header::ACCEPT_ENCODING => Ok(None),
-            header::CONNECTION => Ok(None),
-            header::HOST => patch_host(key, value, &host, proxy_host).map(Some),
+            ref key if is_hop_by_hop(key) => Ok(None),
+            // NOTE: the request body may be rewritten
+            header::CONTENT_LENGTH => Ok(None),
+            // NOTE: the cookies are merged with the jar ones
+            header::COOKIE => Ok(None),
+            // NOTE: the upstream host is given by the URL, which becomes `:authority` over HTTP/2
+            header::HOST => Ok(None),
             header::ORIGIN | header::REFERER => {
                 patch_host(key, value, &base_url_with_host, proxy_base_url_with_host)
                     .and_then(|value| patch_host(key, &value, &host, proxy_host))
                     .map(Some)
             }
             ref key if key == header::HeaderName::from_static("x-forwarded-host") => Ok(None),
+            ref key if telemetry::is_propagation_header(key.as_str()) => Ok(None),
             _ => Ok(Some(value.clone())),
         } {
-            Ok(Some(value)) => builder = builder.header(key, value),
+            Ok(Some(value)) => {
+                if key == header::ORIGIN {
+                    upstream_origin = Some(value.clone());
+                }
+                headers.append(key, value);
+            }
             Ok(None) => {}
-            Err(e) => return HttpResponse::Forbidden().body(e.to_string()),
+            Err(e) => {
+                telemetry::set_error(&span, &e);
+                return HttpResponse::Forbidden().body(e.to_string());
+            }
         }
     }
+    telemetry::end(&span);
 
     // load a payload, which is a stream of Bytes objects
+    let span = telemetry::start(cx, "read request body");
     let body = 'body: {
         match method {
             Method::PATCH | Method::POST | Method::PUT => {
@@ -147,94 +307,302 @@ async fn resolve(
             _ => Ok(None),
         }
     };
-    builder = match body {
-        Ok(Some(body)) => builder.body(body),
-        Ok(None) => builder,
+    telemetry::end(&span);
+
+    // define a request, filtering its body if any
+    let mut builder = match body {
+        Ok(Some(body)) => {
+            let mut parts = RequestParts {
+                method: method.clone(),
+                headers,
+                body,
+            };
+            if let Err(e) = request_filters
+                .filter(&config_map, &mut parts)
+                .with_context(cx.clone())
+                .await
+            {
+                return HttpResponse::Forbidden()
+                    .body(format!("failed to filter the request: {e}"));
+            }
+
+            metrics::REQUEST_BYTES
+                .with_label_values(&[method.as_str()])
+                .inc_by(parts.body.len() as u64);
+            client
+                .request(parts.method, proxy_url.clone())
+                .headers(parts.headers)
+                .body(parts.body)
+        }
+        Ok(None) => client
+            .request(method.clone(), proxy_url.clone())
+            .headers(headers),
         Err(e) => return HttpResponse::Forbidden().body(e.to_string()),
     };
+    if let Some(value) = cookies.request_header(req, session.as_ref(), &proxy_url) {
+        builder = builder.header(header::COOKIE, value);
+    }
 
     // call a proxy request
-    let (res, status) = match builder.send().await {
+    let span = telemetry::start_client(cx, format!("{method} {upstream_host}"));
+    for (key, value) in telemetry::inject(&span) {
+        builder = builder.header(key, value);
+    }
+
+    let timer = Instant::now();
+    let (res, status, elapsed) = match builder.send().await {
         Ok(res) => {
             let status = res.status();
-            info!("[{method}] {peer_addr} => /{path}{query} => {status}");
-            (res, status)
+            let elapsed = timer.elapsed();
+            metrics::observe_upstream(method.as_str(), status.as_str(), elapsed);
+            telemetry::set_status_code(&span, status.as_u16());
+            telemetry::end(&span);
+            (res, status, elapsed)
         }
         Err(e) => {
-            return HttpResponse::Forbidden().body(format!("failed to find the url (/{path}): {e}"))
+            metrics::observe_upstream_error(&e);
+            telemetry::set_error(&span, &e);
+            telemetry::end(&span);
+            return HttpResponse::Forbidden()
+                .body(format!("failed to find the url (/{path}): {e}"));
         }
     };
 
+    config_map.extend_with_response(&res);
+
     // define a response builder
     let mut builder = HttpResponse::build(status);
-    for (key, value) in res.headers() {
-        match *key {
-            header::CONTENT_ENCODING => {}
-            header::CONTENT_LENGTH => {}
-            header::CONTENT_SECURITY_POLICY => {}
-            _ => match patch_host(key, value, proxy_host, &host) {
-                Ok(value) => {
-                    builder.append_header((key, value));
+    builder.extensions_mut().insert(UpstreamInfo {
+        host: upstream_host.into(),
+        elapsed,
+    });
+
+    // patch the upstream and the sibling origins in the response headers
+    let hosts: Vec<_> = ::std::iter::once((proxy_host.clone(), host.clone()))
+        .chain(origins.iter().map(|origin| {
+            (
+                format!("//{origin}", origin = origin.host),
+                format!(
+                    "//{host}{base_url}{prefix}{origin}",
+                    origin = origin.host,
+                    prefix = origin::PATH_PREFIX,
+                ),
+            )
+        }))
+        .collect();
+
+    fn append_headers(
+        builder: &mut HttpResponseBuilder,
+        headers: &header::HeaderMap,
+        hosts: &[(String, String)],
+    ) -> Result<()> {
+        for (key, value) in headers {
+            match *key {
+                header::CONTENT_ENCODING => {}
+                header::CONTENT_LENGTH => {}
+                header::CONTENT_SECURITY_POLICY => {}
+                ref key if is_hop_by_hop(key) => {}
+                // NOTE: the cookies and the CORS headers are appended separately
+                header::SET_COOKIE => {}
+                ref key if Cors::is_cors_header(key) => {}
+                _ => {
+                    builder.append_header((key, patch_hosts(key, value, hosts)?));
                 }
-                Err(e) => return HttpResponse::Forbidden().body(e.to_string()),
-            },
+            }
         }
+        Ok(())
     }
 
+    let cookie_scope = CookieScope {
+        is_secure: scheme == "https",
+        base_url,
+        upstream_base_url,
+        proxy_base_url: &local_base_url,
+    };
+    let append_cookie_and_cors_headers =
+        |builder: &mut HttpResponseBuilder, headers: &header::HeaderMap| {
+            for value in cookies.response_headers(
+                session.as_ref(),
+                &proxy_url,
+                headers.get_all(header::SET_COOKIE).iter(),
+                &cookie_scope,
+            ) {
+                builder.append_header((header::SET_COOKIE, value));
+            }
+            for header in cors.response_headers(req, upstream_origin.as_ref(), headers) {
+                builder.append_header(header);
+            }
+        };
+
     fn respond_pass_through(
         mut builder: HttpResponseBuilder,
-        res: ::reqwest::Response,
+        method: &Method,
+        content_length: Option<u64>,
+        stream: impl Stream<Item = ::reqwest::Result<Bytes>> + 'static,
     ) -> HttpResponse {
-        if let Some(content_length) = res.content_length() {
+        if let Some(content_length) = content_length {
             builder.no_chunking(content_length);
         }
-        builder.streaming(res.bytes_stream())
+
+        let response_bytes = metrics::RESPONSE_BYTES.with_label_values(&[method.as_str()]);
+        builder.streaming(stream.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                response_bytes.inc_by(chunk.len() as u64);
+            }
+        }))
+    }
+
+    /// The response read up to the limit, and the rest of its body to be streamed through
+    type Overflowed = (
+        ResponseParts,
+        LocalBoxStream<'static, ::reqwest::Result<Bytes>>,
+    );
+
+    async fn read_parts(
+        cx: &::opentelemetry::Context,
+        res: ::reqwest::Response,
+        limit: Option<usize>,
+    ) -> ::reqwest::Result<Result<ResponseParts, Overflowed>> {
+        let span = telemetry::start(cx, "read response body");
+        let status = res.status();
+        let headers = res.headers().clone();
+        let Some(limit) = limit else {
+            let body = res.bytes().await;
+            telemetry::end(&span);
+            return Ok(Ok(ResponseParts {
+                status,
+                headers,
+                body: body?,
+            }));
+        };
+
+        let mut stream = res.bytes_stream();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk?);
+            if body.len() > limit {
+                telemetry::end(&span);
+                let parts = ResponseParts {
+                    status,
+                    headers,
+                    body: body.freeze(),
+                };
+                return Ok(Err((parts, stream.boxed_local())));
+            }
+        }
+        telemetry::end(&span);
+        Ok(Ok(ResponseParts {
+            status,
+            headers,
+            body: body.freeze(),
+        }))
     }
 
-    // send a response
-    match res.headers().get(header::CONTENT_TYPE) {
+    // check whether any filter accepts the response, and how much of it to buffer
+    let limit = match res.headers().get(header::CONTENT_TYPE) {
         Some(content_type) => match content_type.to_str() {
             Ok(content_type) => match content_type.parse::<::mime::Mime>() {
-                Ok(mime) => match mime.subtype() {
-                    ::mime::HTML => match res.text().await {
-                        Ok(body) => builder.body(filters.filter(&config_map, body)),
-                        Err(e) => HttpResponse::Forbidden()
-                            .body(format!("failed to parse the response body as string: {e}")),
-                    },
-                    _ => respond_pass_through(builder, res),
-                },
-                Err(e) => HttpResponse::Forbidden()
-                    .body(format!("failed to parse the response content type: {e}")),
+                Ok(mime) if url_filters.accepts(&mime) || filters.accepts(&mime) => {
+                    // NOTE: the large ones other than HTML, e.g. the bundled scripts,
+                    // are streamed through as is
+                    let limit = (mime.subtype() != ::mime::HTML).then_some(*filters_max_body_size);
+                    Some(limit).filter(|limit| {
+                        limit.is_none_or(|limit| {
+                            res.content_length().is_none_or(|len| len <= limit as u64)
+                        })
+                    })
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    return HttpResponse::Forbidden()
+                        .body(format!("failed to parse the response content type: {e}"))
+                }
             },
-            Err(e) => HttpResponse::Forbidden().body(format!(
-                "failed to parse the response content type as string: {e}"
-            )),
+            Err(e) => {
+                return HttpResponse::Forbidden().body(format!(
+                    "failed to parse the response content type as string: {e}"
+                ))
+            }
         },
-        None => respond_pass_through(builder, res),
+        None => None,
+    };
+
+    // send a response
+    let Some(limit) = limit else {
+        if let Err(e) = append_headers(&mut builder, res.headers(), &hosts) {
+            return HttpResponse::Forbidden().body(e.to_string());
+        }
+        append_cookie_and_cors_headers(&mut builder, res.headers());
+        return respond_pass_through(builder, &method, res.content_length(), res.bytes_stream());
+    };
+
+    let content_length = res.content_length();
+    let mut parts = match read_parts(cx, res, limit).await {
+        Ok(Ok(parts)) => parts,
+        Ok(Err((parts, rest))) => {
+            if let Err(e) = append_headers(&mut builder, &parts.headers, &hosts) {
+                return HttpResponse::Forbidden().body(e.to_string());
+            }
+            append_cookie_and_cors_headers(&mut builder, &parts.headers);
+            let stream = ::futures::stream::once(async move { Ok(parts.body) }).chain(rest);
+            return respond_pass_through(builder, &method, content_length, stream);
+        }
+        Err(e) => {
+            return HttpResponse::Forbidden().body(format!("failed to read the response body: {e}"))
+        }
+    };
+    let encoding = charset::decode(&mut parts);
+    // NOTE: the URL filters of the stylesheets and scripts, then the other filters
+    for filters in [url_filters, filters] {
+        if let Err(e) = filters
+            .filter(&config_map, &mut parts)
+            .with_context(cx.clone())
+            .await
+        {
+            return HttpResponse::Forbidden().body(format!("failed to filter the response: {e}"));
+        }
     }
+    charset::encode(&mut parts, encoding);
+
+    builder.status(parts.status);
+    if let Err(e) = append_headers(&mut builder, &parts.headers, &hosts) {
+        return HttpResponse::Forbidden().body(e.to_string());
+    }
+    append_cookie_and_cors_headers(&mut builder, &parts.headers);
+    metrics::RESPONSE_BYTES
+        .with_label_values(&[method.as_str()])
+        .inc_by(parts.body.len() as u64);
+    builder.body(parts.body)
 }
 
 struct Context {
+    access_log: Arc<AccessLog>,
     client: Client,
+    client_config: ClientConfig,
     config: Config,
     config_map: ConfigMap,
+    cookies: Cookies,
+    cors: Cors,
     filters: ResponseFilters,
+    origins: Origins,
+    request_filters: RequestFilters,
+    url_filters: ResponseFilters,
+    /// Size of the largest responses other than HTML to be buffered for the filters
+    filters_max_body_size: usize,
 }
 
 #[actix_web::main]
 async fn main() {
-    async fn try_main() -> Result<()> {
+    async fn try_main(overrides: Overrides) -> Result<()> {
         // Initialize kubernetes client
-        let addr = env::infer::<_, SocketAddr>("BIND_ADDR")
+        let addr = overrides
+            .infer::<SocketAddr>("BIND_ADDR")
             .unwrap_or_else(|_| "0.0.0.0:80".parse().unwrap());
 
         // Initialize client
-        let client = {
-            let builder = ::reqwest::ClientBuilder::new();
-            builder
-                .build()
-                .map_err(|e| anyhow!("failed to init reqwest client: {e}"))?
-        };
+        let client_config = ClientConfig::try_from_env("PROXY_", &overrides)
+            .map_err(|e| anyhow!("failed to parse client config: {e}"))?;
+        let client = client_config.try_build()?;
         // let client = {
         //     let mut builder = ::reqwest_middleware::ClientBuilder::new(client);
         //     if env::infer::<_, bool>("CACHE_ENABLE").unwrap_or_default() {
@@ -253,36 +621,147 @@ async fn main() {
         // };
 
         // Initialize config
-        let config = Config::try_default().map_err(|e| anyhow!("failed to parse config: {e}"))?;
+        let config =
+            Config::try_default(&overrides).map_err(|e| anyhow!("failed to parse config: {e}"))?;
         let config_map = config.to_map();
 
+        // Initialize sibling origins
+        let origins = Origins::try_from_config(&config, &client)?;
+
         // Initialize filter
-        let filters = DefaultResponseFilter.try_build()?;
+        let filters = DefaultResponseFilter.try_build(&config_map)?;
+        let request_filters = DefaultRequestFilter.try_build(&config_map)?;
+        let url_filters = DefaultUrlResponseFilter.try_build(&config_map)?;
+        let filters_max_body_size = overrides
+            .infer::<usize>("FILTER_MAX_BODY_SIZE_MB")
+            .unwrap_or(4)
+            << 20;
+
+        // Initialize cookies
+        let cookies = Cookies::try_default(&overrides)
+            .map_err(|e| anyhow!("failed to parse cookie config: {e}"))?;
+
+        // Initialize CORS
+        let cors = Cors::try_default(&overrides)
+            .map_err(|e| anyhow!("failed to parse CORS config: {e}"))?;
+
+        // Initialize access log
+        let access_log = AccessLog::try_default(&overrides)
+            .map(Arc::new)
+            .map_err(|e| anyhow!("failed to init access log: {e}"))?;
 
         let context = web::Data::new(Context {
+            access_log,
             client,
+            client_config,
             config,
             config_map,
+            cookies,
+            cors,
             filters,
+            origins,
+            request_filters,
+            url_filters,
+            filters_max_body_size,
         });
 
         // Initialize path
         let path = format!("{base_url}{{path:.*}}", base_url = &context.config.base_url,);
+        let shim_path = context.config.shim_url.clone();
+        let service_worker_path = context.config.shim_service_worker_url.clone();
+
+        // Initialize TLS
+        let tls = TlsConfig::try_default(&overrides)
+            .map_err(|e| anyhow!("failed to parse TLS config: {e}"))?;
+
+        // Start admin server
+        let admin = AdminConfig::try_default(&overrides)
+            .map_err(|e| anyhow!("failed to parse admin config: {e}"))?;
+        let probes = admin.probes(&context.config.base_url);
+        let admin_server = admin.serve(web::Data::clone(&context));
 
         // Start web server
-        HttpServer::new(move || {
+        let server = HttpServer::new(move || {
             App::new()
                 .app_data(web::Data::clone(&context))
+                .configure(probes.clone())
+                .route(&shim_path, web::get().to(shim::serve))
+                .route(
+                    &service_worker_path,
+                    web::get().to(shim::serve_service_worker),
+                )
                 .route(&path, web::route().to(resolve))
-        })
-        .bind(addr)
-        .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"))
+        });
+        // NOTE: HTTP/2 is negotiated with ALPN over TLS, and h2c is accepted with prior knowledge
+        let server = match &tls {
+            Some(tls) => {
+                let tls_addr = tls.addr;
+                let (tls_config, resolver) = tls.try_build()?;
+                tls.spawn_reloader(resolver);
+
+                server
+                    .bind_rustls_021(tls_addr, tls_config)
+                    .unwrap_or_else(|e| panic!("failed to bind to {tls_addr}: {e}"))
+            }
+            None => server,
+        };
+        let server = if tls.as_ref().is_some_and(|tls| tls.redirect_http) {
+            server
+        } else if overrides.infer("BIND_H2C").unwrap_or(true) {
+            server
+                .bind_auto_h2c(addr)
+                .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"))
+        } else {
+            server
+                .bind(addr)
+                .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"))
+        }
         .shutdown_timeout(20)
-        .run()
-        .await
-        .map_err(Into::into)
+        .run();
+
+        // Start HTTP to HTTPS redirect server
+        let redirect_server = async {
+            match tls.filter(|tls| tls.redirect_http) {
+                Some(tls) => {
+                    let tls_addr = web::Data::new(tls.addr);
+                    HttpServer::new(move || {
+                        App::new()
+                            .app_data(web::Data::clone(&tls_addr))
+                            .default_service(web::route().to(tls::redirect))
+                    })
+                    .bind(addr)
+                    .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"))
+                    .shutdown_timeout(20)
+                    .run()
+                    .await
+                }
+                None => Ok(()),
+            }
+        };
+
+        ::futures::try_join!(server, redirect_server, admin_server)
+            .map(|((), (), ())| ())
+            .map_err(Into::into)
     }
 
     logger::init_once();
-    try_main().await.expect("running a server")
+    let overrides = match Cli::parse().command.unwrap_or_default() {
+        Command::Serve(args) => args.overrides(),
+        command => {
+            if let Err(e) = command.run().await {
+                eprintln!("error: {e:#}");
+                ::std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    let tracer_provider = telemetry::try_init().expect("initializing telemetry");
+    let result = try_main(overrides).await;
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            warn!("failed to shutdown the tracer provider: {e}");
+        }
+    }
+    result.expect("running a server")
 }
@@ -0,0 +1,176 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    web::Bytes,
+    HttpResponse,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+macro_rules! namespaced {
+    ( $name:expr ) => {
+        concat!("open_transparent_proxy_", $name)
+    };
+}
+
+pub static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        namespaced!("requests_total"),
+        "Number of handled requests",
+        &["method", "status", "route"],
+    )
+    .unwrap()
+});
+
+pub static REQUESTS_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        namespaced!("requests_in_flight"),
+        "Number of requests being handled",
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        namespaced!("upstream_duration_seconds"),
+        "Time to receive the response headers from the upstream",
+        &["method", "status"],
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        namespaced!("upstream_errors_total"),
+        "Number of failed upstream requests",
+        &["kind"],
+    )
+    .unwrap()
+});
+
+pub static REQUEST_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        namespaced!("request_bytes_total"),
+        "Size of the request payloads forwarded to the upstream",
+        &["method"],
+    )
+    .unwrap()
+});
+
+pub static RESPONSE_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        namespaced!("response_bytes_total"),
+        "Size of the response payloads sent to the clients",
+        &["method"],
+    )
+    .unwrap()
+});
+
+pub static FILTER_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        namespaced!("filter_duration_seconds"),
+        "Time to apply a response filter",
+        &["filter"],
+        vec![0.000_01, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5],
+    )
+    .unwrap()
+});
+
+#[cfg(any(
+    feature = "filter-html",
+    feature = "filter-notion",
+    feature = "filter-origin"
+))]
+pub static FILTER_REWRITES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        namespaced!("filter_rewrites_total"),
+        "Number of rewrites made by a response filter",
+        &["filter"],
+    )
+    .unwrap()
+});
+
+pub fn observe_upstream(method: &str, status: &str, elapsed: Duration) {
+    UPSTREAM_DURATION
+        .with_label_values(&[method, status])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn observe_upstream_error(error: &::reqwest::Error) {
+    let kind = if error.is_timeout() {
+        "timeout"
+    } else if error.is_connect() {
+        "connect"
+    } else if error.is_request() {
+        "request"
+    } else {
+        "other"
+    };
+    UPSTREAM_ERRORS.with_label_values(&[kind]).inc();
+}
+
+pub fn observe_request(method: &str, status: &str, route: &str) {
+    REQUESTS.with_label_values(&[method, status, route]).inc();
+}
+
+/// Counts the request as in-flight until dropped
+pub struct InFlightGuard(());
+
+impl InFlightGuard {
+    pub fn enter() -> Self {
+        REQUESTS_IN_FLIGHT.inc();
+        Self(())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        REQUESTS_IN_FLIGHT.dec();
+    }
+}
+
+/// Keep the request in-flight until the response body is completed
+pub fn wrap_in_flight(guard: InFlightGuard, res: HttpResponse) -> HttpResponse {
+    res.map_body(move |_, body| {
+        BoxBody::new(InFlightBody {
+            inner: body,
+            _guard: guard,
+        })
+    })
+}
+
+struct InFlightBody {
+    inner: BoxBody,
+    _guard: InFlightGuard,
+}
+
+impl MessageBody for InFlightBody {
+    type Error = Box<dyn ::std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.inner.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_next(cx)
+    }
+}
+
+pub fn encode() -> ::anyhow::Result<String> {
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&::prometheus::gather(), &mut buf)
+        .map_err(|e| ::anyhow::anyhow!("failed to encode metrics: {e}"))?;
+    String::from_utf8(buf).map_err(Into::into)
+}
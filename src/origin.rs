@@ -0,0 +1,183 @@
+use anyhow::{anyhow, bail, Result};
+use reqwest::{Client, Url};
+use serde::Serialize;
+
+use crate::{client::ClientConfig, config::Config};
+
+/// Path prefix of the sibling origins, e.g. `{base_url}_origin/cdn.example.com/...`
+pub const PATH_PREFIX: &str = "_origin/";
+
+/// Sibling origin of the upstream, such as a CDN or an API host
+#[derive(Serialize)]
+pub struct Origin {
+    pub scheme: String,
+    pub host: String,
+    #[serde(rename = "client")]
+    client_config: Option<ClientConfig>,
+    #[serde(skip)]
+    pub client: Client,
+}
+
+#[derive(Default, Serialize)]
+pub struct Origins(Vec<Origin>);
+
+impl Origins {
+    /// Load the origins of `PROXY_ORIGINS`, sharing the upstream client
+    /// unless the origin has its own options, e.g. `PROXY_ORIGIN_CDN_EXAMPLE_COM_TLS_CA_FILE`.
+    pub fn try_from_config(config: &Config, client: &Client) -> Result<Self> {
+        let origins = parse(&config.proxy_origins, &config.proxy_scheme)?;
+        check_env_prefixes(&origins)?;
+
+        origins
+            .into_iter()
+            .map(|(scheme, host)| {
+                let prefix = env_prefix(&host);
+                let (client_config, client) = if ::std::env::vars()
+                    .any(|(key, _)| key.starts_with(&prefix))
+                {
+                    let client_config = ClientConfig::try_from_env(&prefix, &Default::default())
+                        .map_err(|e| {
+                            anyhow!("failed to parse client config of the origin ({host}): {e}")
+                        })?;
+                    let client = client_config.try_build()?;
+                    (Some(client_config), client)
+                } else {
+                    (None, client.clone())
+                };
+
+                Ok(Origin {
+                    scheme,
+                    host,
+                    client_config,
+                    client,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Origin> {
+        self.0.iter()
+    }
+
+    /// Split the path relative to the base URL into the origin and the path on it,
+    /// e.g. `_origin/cdn.example.com/style.css` into `cdn.example.com` and `style.css`.
+    ///
+    /// Returns an error if the path refers to an unknown origin.
+    pub fn split_path<'a>(&self, path: &'a str) -> Option<Result<(&Origin, &'a str)>> {
+        let path = path.strip_prefix(PATH_PREFIX)?;
+        let (host, path) = path.split_once('/').unwrap_or((path, ""));
+        Some(
+            self.0
+                .iter()
+                .find(|origin| origin.host.eq_ignore_ascii_case(host))
+                .map(|origin| (origin, path))
+                .ok_or_else(|| anyhow!("unknown origin: {host}")),
+        )
+    }
+
+    /// Name the route of the path relative to the base URL, either `upstream` or `_origin/{host}`
+    pub fn route(&self, path: &str) -> String {
+        match self.split_path(path) {
+            Some(Ok((origin, _))) => format!("{PATH_PREFIX}{host}", host = origin.host),
+            // NOTE: never label with the hosts given by the clients
+            Some(Err(_)) => "unknown".into(),
+            None => "upstream".into(),
+        }
+    }
+
+    /// Resolve the path relative to the base URL into the upstream URL and the client to fetch it,
+    /// rejecting anything out of the upstream and the sibling origins
+    pub fn resolve_url<'a>(
+        &'a self,
+        config: &Config,
+        client: &'a Client,
+        path: &str,
+    ) -> Result<(&'a Client, Url)> {
+        if path.contains("://") {
+            bail!("expected a path under the base URL, not an absolute URL: {path}");
+        }
+        let path = path.trim_start_matches('/');
+
+        let (client, scheme, host, url) = match self.split_path(path).transpose()? {
+            Some((origin, path)) => (
+                &origin.client,
+                &origin.scheme,
+                &origin.host,
+                format!(
+                    "{scheme}://{host}/{path}",
+                    scheme = origin.scheme,
+                    host = origin.host
+                ),
+            ),
+            None => (
+                client,
+                &config.proxy_scheme,
+                &config.proxy_host,
+                format!(
+                    "{scheme}://{host}{base_url}{path}",
+                    scheme = config.proxy_scheme,
+                    host = config.proxy_host,
+                    base_url = config.proxy_base_url,
+                ),
+            ),
+        };
+
+        let parse = |url: &str| {
+            url.parse::<Url>()
+                .map_err(|e| anyhow!("invalid upstream URL ({url}): {e}"))
+        };
+        let url = parse(&url)?;
+        if url.origin() != parse(&format!("{scheme}://{host}/"))?.origin() {
+            bail!("path out of the upstream: {path}");
+        }
+        Ok((client, url))
+    }
+}
+
+/// Parse the comma-separated origins, e.g. `cdn.example.com,http://legacy.example.com`,
+/// into the schemes and hosts
+pub fn parse(origins: &str, default_scheme: &str) -> Result<Vec<(String, String)>> {
+    origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            let (scheme, host) = origin.split_once("://").unwrap_or((default_scheme, origin));
+            let host = host.trim_end_matches('/');
+            if !matches!(scheme, "http" | "https") {
+                bail!("unsupported scheme of the origin: {origin}");
+            }
+            if host.is_empty() || host.contains(['/', '?', '#', '@']) {
+                bail!("invalid host of the origin: {origin}");
+            }
+            Ok((scheme.into(), host.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Reject the origins whose env prefixes collide, e.g. `cdn.example.com` and `cdn-example.com`,
+/// or overlap, e.g. `cdn.example` and `cdn.example.com`, as they would share the options
+fn check_env_prefixes(origins: &[(String, String)]) -> Result<()> {
+    for (index, (_, host)) in origins.iter().enumerate() {
+        let prefix = env_prefix(host);
+        if let Some((_, other)) = origins[..index].iter().find(|(_, other)| {
+            let other = env_prefix(other);
+            other.starts_with(&prefix) || prefix.starts_with(&other)
+        }) {
+            bail!("ambiguous env prefix of the origins ({prefix}): {other}, {host}");
+        }
+    }
+    Ok(())
+}
+
+fn env_prefix(host: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("PROXY_ORIGIN_{host}_")
+}
@@ -0,0 +1,79 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use actix_web::{http::header, web, HttpResponse, Responder};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::config::Config;
+
+/// Reserved path of the client-side shim, relative to `BASE_URL`
+pub const PATH: &str = "__proxy/shim.js";
+
+/// Reserved path of the service worker, relative to `BASE_URL`
+pub const SERVICE_WORKER_PATH: &str = "__proxy/sw.js";
+
+macro_rules! script {
+    ( $file:expr ) => {
+        concat!(
+            "(function () {\n'use strict';\n",
+            include_str!("rewrite.js"),
+            include_str!($file),
+            "})();\n",
+        )
+    };
+}
+
+const SCRIPT: &str = script!("shim.js");
+const SERVICE_WORKER: &str = script!("sw.js");
+const SERVICE_WORKER_DISABLED: &str = include_str!("sw-disabled.js");
+
+/// Version of the shim, which changes with its content
+pub static VERSION: Lazy<String> = Lazy::new(|| {
+    let mut hasher = DefaultHasher::new();
+    SCRIPT.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+});
+
+#[derive(Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    v: Option<String>,
+}
+
+/// Serve the shim, which reads the per-request config from the `data-*` attributes of its tag
+pub async fn serve(query: web::Query<Query>) -> impl Responder {
+    // NOTE: cache forever only if the versioned URL is requested
+    let cache_control = match &query.v {
+        Some(version) if *version == *VERSION => "public, max-age=31536000, immutable",
+        _ => "no-cache",
+    };
+
+    crate::metrics::observe_request("GET", "200", "shim");
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/javascript; charset=utf-8"))
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .insert_header((header::ETAG, format!("\"{}\"", *VERSION)))
+        .body(SCRIPT)
+}
+
+/// Serve the service worker, which rewrites the in-scope fetches if `SHIM_SERVICE_WORKER` is set
+pub async fn serve_service_worker(context: web::Data<crate::Context>) -> impl Responder {
+    let Config {
+        base_url,
+        shim_service_worker,
+        ..
+    } = &context.config;
+
+    let script = if shim_service_worker == "true" {
+        SERVICE_WORKER
+    } else {
+        SERVICE_WORKER_DISABLED
+    };
+
+    crate::metrics::observe_request("GET", "200", "shim");
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/javascript; charset=utf-8"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header(("service-worker-allowed", base_url.as_str()))
+        .body(script)
+}
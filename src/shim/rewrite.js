@@ -0,0 +1,48 @@
+// Create a function to rewrite the URL pointing at the proxy's root or the upstream into the proxied one
+function createRewriter(baseUrl, upstream, location) {
+  return function rewriteUrl(value) {
+    if (value === null || value === undefined) {
+      return value;
+    }
+    var raw = String(value).trim();
+    if (raw === '' || raw.charAt(0) === '#') {
+      return value;
+    }
+    // NOTE: skip data:, blob:, javascript:, mailto:, etc.
+    if (/^[a-z][a-z0-9+.-]*:/i.test(raw) && !/^(https?|wss?):/i.test(raw)) {
+      return value;
+    }
+
+    var url;
+    try {
+      url = new URL(raw, location.href);
+    } catch (e) {
+      return value;
+    }
+
+    var isWebSocket = url.protocol === 'ws:' || url.protocol === 'wss:';
+    var protocol = isWebSocket ? url.protocol.replace('ws', 'http') : url.protocol;
+
+    var path;
+    if (
+      protocol === upstream.protocol &&
+      url.host === upstream.host &&
+      url.pathname.startsWith(upstream.pathname)
+    ) {
+      path = url.pathname.slice(upstream.pathname.length);
+    } else if (url.host === location.host) {
+      if (url.pathname.startsWith(baseUrl)) {
+        return value;
+      }
+      path = url.pathname.slice(1);
+    } else {
+      return value;
+    }
+
+    var target = new URL(baseUrl + path + url.search + url.hash, location.origin);
+    if (isWebSocket) {
+      target.protocol = location.protocol === 'https:' ? 'wss:' : 'ws:';
+    }
+    return target.href;
+  };
+}
@@ -0,0 +1,227 @@
+var script = document.currentScript;
+var baseUrl = script.dataset.baseUrl;
+var upstream = new URL(script.dataset.upstream);
+var rewriteUrl = createRewriter(baseUrl, upstream, location);
+
+var URL_ATTRIBUTES = ['action', 'data', 'formaction', 'href', 'poster', 'src', 'url'];
+
+function rewriteAttributes(node) {
+  if (!(node instanceof Element)) {
+    return;
+  }
+  URL_ATTRIBUTES.forEach(function (name) {
+    if (node.hasAttribute(name)) {
+      var value = node.getAttribute(name);
+      var rewritten = rewriteUrl(value);
+      if (rewritten !== value) {
+        setAttribute.call(node, name, rewritten);
+      }
+    }
+  });
+}
+
+function wrapMethod(target, name, wrap) {
+  if (target && typeof target[name] === 'function') {
+    target[name] = wrap(target[name]);
+  }
+}
+
+function wrapConstructor(name, index) {
+  var Native = window[name];
+  if (typeof Native !== 'function') {
+    return;
+  }
+  window[name] = new Proxy(Native, {
+    construct: function (target, args, newTarget) {
+      args[index] = rewriteUrl(args[index]);
+      return Reflect.construct(target, args, newTarget === window[name] ? target : newTarget);
+    },
+  });
+}
+
+/* Attributes and properties */
+
+var setAttribute = Element.prototype.setAttribute;
+Element.prototype.setAttribute = function (name, value) {
+  if (URL_ATTRIBUTES.indexOf(String(name).toLowerCase()) !== -1) {
+    value = rewriteUrl(value);
+  }
+  return setAttribute.call(this, name, value);
+};
+
+[
+  ['HTMLAnchorElement', 'href'],
+  ['HTMLAreaElement', 'href'],
+  ['HTMLBaseElement', 'href'],
+  ['HTMLEmbedElement', 'src'],
+  ['HTMLFormElement', 'action'],
+  ['HTMLIFrameElement', 'src'],
+  ['HTMLImageElement', 'src'],
+  ['HTMLInputElement', 'src'],
+  ['HTMLLinkElement', 'href'],
+  ['HTMLMediaElement', 'src'],
+  ['HTMLObjectElement', 'data'],
+  ['HTMLScriptElement', 'src'],
+  ['HTMLSourceElement', 'src'],
+  ['HTMLTrackElement', 'src'],
+  ['HTMLVideoElement', 'poster'],
+].forEach(function (entry) {
+  var type = window[entry[0]];
+  var descriptor = type && Object.getOwnPropertyDescriptor(type.prototype, entry[1]);
+  if (!descriptor || !descriptor.set) {
+    return;
+  }
+  Object.defineProperty(type.prototype, entry[1], {
+    configurable: true,
+    enumerable: descriptor.enumerable,
+    get: descriptor.get,
+    set: function (value) {
+      descriptor.set.call(this, rewriteUrl(value));
+    },
+  });
+});
+
+/* DOM insertions */
+
+['appendChild', 'insertBefore', 'replaceChild'].forEach(function (name) {
+  wrapMethod(Node.prototype, name, function (native) {
+    return function (node) {
+      rewriteAttributes(node);
+      return native.apply(this, arguments);
+    };
+  });
+});
+
+['append', 'prepend', 'before', 'after', 'replaceWith'].forEach(function (name) {
+  wrapMethod(Element.prototype, name, function (native) {
+    return function () {
+      [].forEach.call(arguments, rewriteAttributes);
+      return native.apply(this, arguments);
+    };
+  });
+});
+
+// NOTE: catch the elements created by the parser or innerHTML
+new MutationObserver(function (records) {
+  records.forEach(function (record) {
+    record.addedNodes.forEach(function (node) {
+      rewriteAttributes(node);
+      if (node.querySelectorAll) {
+        node.querySelectorAll('[' + URL_ATTRIBUTES.join('],[') + ']').forEach(rewriteAttributes);
+      }
+    });
+  });
+}).observe(document.documentElement, { childList: true, subtree: true });
+
+/* Network */
+
+wrapMethod(window, 'fetch', function (native) {
+  return function (resource, init) {
+    if (typeof Request !== 'undefined' && resource instanceof Request) {
+      var url = rewriteUrl(resource.url);
+      if (url !== resource.url) {
+        resource = new Request(url, resource);
+      }
+    } else {
+      resource = rewriteUrl(resource);
+    }
+    return arguments.length > 1 ? native.call(this, resource, init) : native.call(this, resource);
+  };
+});
+
+wrapMethod(XMLHttpRequest.prototype, 'open', function (native) {
+  return function (method, url) {
+    var args = [].slice.call(arguments);
+    args[1] = rewriteUrl(url);
+    return native.apply(this, args);
+  };
+});
+
+wrapMethod(navigator, 'sendBeacon', function (native) {
+  return function (url, data) {
+    return native.call(navigator, rewriteUrl(url), data);
+  };
+});
+
+wrapConstructor('WebSocket', 0);
+wrapConstructor('EventSource', 0);
+wrapConstructor('Worker', 0);
+wrapConstructor('SharedWorker', 0);
+
+/* Navigation */
+
+['pushState', 'replaceState'].forEach(function (name) {
+  wrapMethod(History.prototype, name, function (native) {
+    return function (state, title, url) {
+      var args = [].slice.call(arguments);
+      if (args.length > 2) {
+        args[2] = rewriteUrl(url);
+      }
+      return native.apply(this, args);
+    };
+  });
+});
+
+// NOTE: keep the upstream from rewriting the URL on load unless bypassed, e.g. for Notion
+if (script.dataset.guardReplaceState === 'true') {
+  history.replaceState = function (state, title) {
+    if (title !== 'bypass') {
+      return;
+    }
+    return History.prototype.replaceState.apply(history, arguments);
+  };
+}
+
+wrapMethod(window, 'open', function (native) {
+  return function (url) {
+    var args = [].slice.call(arguments);
+    args[0] = rewriteUrl(url);
+    return native.apply(window, args);
+  };
+});
+
+document.addEventListener(
+  'submit',
+  function (event) {
+    var submitter = event.submitter;
+    if (submitter && submitter.hasAttribute('formaction')) {
+      rewriteAttributes(submitter);
+    }
+    rewriteAttributes(event.target);
+  },
+  true
+);
+
+wrapMethod(HTMLFormElement.prototype, 'submit', function (native) {
+  return function () {
+    rewriteAttributes(this);
+    return native.apply(this, arguments);
+  };
+});
+
+// NOTE: `location` is unforgeable, so catch its assignments with the Navigation API if any
+if (window.navigation && typeof window.navigation.addEventListener === 'function') {
+  window.navigation.addEventListener('navigate', function (event) {
+    if (!event.cancelable || event.hashChange || event.downloadRequest !== null) {
+      return;
+    }
+    var url = event.destination.url;
+    var rewritten = rewriteUrl(url);
+    if (rewritten !== url) {
+      event.preventDefault();
+      location.assign(rewritten);
+    }
+  });
+}
+
+/* Service worker */
+
+if (script.dataset.serviceWorker === 'true' && 'serviceWorker' in navigator) {
+  navigator.serviceWorker
+    .register(script.dataset.serviceWorkerUrl + '?upstream=' + encodeURIComponent(upstream.href), {
+      scope: baseUrl,
+    })
+    .catch(function (error) {
+      console.warn('failed to register the proxy service worker', error);
+    });
+}
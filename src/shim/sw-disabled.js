@@ -0,0 +1,8 @@
+// NOTE: replace the service worker registered before, as the mode is disabled
+self.addEventListener('install', function () {
+  self.skipWaiting();
+});
+
+self.addEventListener('activate', function (event) {
+  event.waitUntil(self.registration.unregister());
+});
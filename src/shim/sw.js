@@ -0,0 +1,27 @@
+var params = new URL(self.location.href).searchParams;
+var baseUrl = new URL(self.registration.scope).pathname;
+var upstream = new URL(params.get('upstream'));
+var rewriteUrl = createRewriter(baseUrl, upstream, self.location);
+
+self.addEventListener('install', function () {
+  self.skipWaiting();
+});
+
+self.addEventListener('activate', function (event) {
+  event.waitUntil(self.clients.claim());
+});
+
+self.addEventListener('fetch', function (event) {
+  var request = event.request;
+  var url = rewriteUrl(request.url);
+  if (url === request.url) {
+    return;
+  }
+
+  // NOTE: navigation requests cannot be cloned into another URL
+  if (request.mode === 'navigate') {
+    event.respondWith(Response.redirect(url, 302));
+  } else {
+    event.respondWith(fetch(new Request(url, request)));
+  }
+});
@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+
+use actix_web::{http::header::HeaderMap, HttpRequest};
+use anyhow::{anyhow, Result};
+use ark_core::env;
+use log::info;
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    propagation::{Extractor, Injector},
+    trace::{SpanBuilder, SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+
+/// Initialize the OTLP exporter if `OTEL_EXPORTER_OTLP_ENDPOINT` is given
+pub fn try_init() -> Result<Option<SdkTracerProvider>> {
+    // NOTE: propagate W3C trace context even if the spans are not exported
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    if env::infer::<_, String>("OTEL_EXPORTER_OTLP_ENDPOINT").is_err()
+        && env::infer::<_, String>("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_err()
+    {
+        return Ok(None);
+    }
+
+    let exporter = ::opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| anyhow!("failed to init OTLP span exporter: {e}"))?;
+
+    let service_name = env::infer::<_, String>("OTEL_SERVICE_NAME")
+        .unwrap_or_else(|_| env!("CARGO_PKG_NAME").into());
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name)
+                .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+                .build(),
+        )
+        .build();
+
+    global::set_tracer_provider(provider.clone());
+    info!("exporting traces over OTLP");
+    Ok(Some(provider))
+}
+
+fn tracer() -> BoxedTracer {
+    global::tracer(env!("CARGO_PKG_NAME"))
+}
+
+/// Start a server span, continuing the trace of the incoming `traceparent` header if any.
+///
+/// The span is named by the route, e.g. `GET upstream`, keeping the path out of the name.
+pub fn start_server(req: &HttpRequest, route: &str) -> Context {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+
+    let builder = SpanBuilder::from_name(format!("{} {route}", req.method()))
+        .with_kind(SpanKind::Server)
+        .with_attributes([
+            KeyValue::new("http.request.method", req.method().to_string()),
+            KeyValue::new("http.route", route.to_string()),
+            KeyValue::new("url.path", req.path().to_string()),
+        ]);
+    let span = tracer().build_with_context(builder, &parent);
+    parent.with_span(span)
+}
+
+/// Start a child span of the given context
+pub fn start(cx: &Context, name: impl Into<Cow<'static, str>>) -> Context {
+    let span = tracer().start_with_context(name, cx);
+    cx.with_span(span)
+}
+
+/// Start a client span of the given context, e.g. an upstream request
+pub fn start_client(cx: &Context, name: impl Into<Cow<'static, str>>) -> Context {
+    let builder = SpanBuilder::from_name(name).with_kind(SpanKind::Client);
+    let span = tracer().build_with_context(builder, cx);
+    cx.with_span(span)
+}
+
+pub fn set_status_code(cx: &Context, status: u16) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("http.response.status_code", status as i64));
+    if status >= 500 {
+        span.set_status(Status::error(status.to_string()));
+    }
+}
+
+pub fn set_error(cx: &Context, error: impl ToString) {
+    cx.span().set_status(Status::error(error.to_string()));
+}
+
+pub fn end(cx: &Context) {
+    cx.span().end();
+}
+
+/// Collect the `traceparent` and `tracestate` headers of the given context
+pub fn inject(cx: &Context) -> Vec<(String, String)> {
+    let mut headers = HeaderInjector::default();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut headers));
+    headers.0
+}
+
+pub fn is_propagation_header(key: &str) -> bool {
+    matches!(key, "traceparent" | "tracestate")
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[derive(Default)]
+struct HeaderInjector(Vec<(String, String)>);
+
+impl Injector for HeaderInjector {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.push((key.into(), value));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use anyhow::{anyhow, bail, Result};
+use ark_core::env;
+use log::{info, warn};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use serde::Serialize;
+
+use crate::config::Overrides;
+
+/// Kubernetes TLS secret keys
+const SNI_CERT_FILE: &str = "tls.crt";
+const SNI_KEY_FILE: &str = "tls.key";
+
+#[derive(Serialize)]
+pub struct TlsConfig {
+    pub addr: SocketAddr,
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub sni_dir: Option<PathBuf>,
+    pub reload_interval: Duration,
+    pub redirect_http: bool,
+}
+
+impl TlsConfig {
+    pub fn try_default(overrides: &Overrides) -> Result<Option<Self>> {
+        let cert_file: PathBuf = match overrides.infer("TLS_CERT_FILE") {
+            Ok(cert_file) => cert_file,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            addr: overrides
+                .infer("TLS_BIND_ADDR")
+                .unwrap_or_else(|_| "0.0.0.0:443".parse().unwrap()),
+            cert_file,
+            key_file: overrides
+                .infer("TLS_KEY_FILE")
+                .map_err(|e| anyhow!("TLS_KEY_FILE is required with TLS_CERT_FILE: {e}"))?,
+            sni_dir: env::infer("TLS_SNI_DIR").ok(),
+            reload_interval: Duration::from_secs(
+                env::infer("TLS_RELOAD_INTERVAL_SECS").unwrap_or(30),
+            ),
+            redirect_http: env::infer("TLS_REDIRECT_HTTP").unwrap_or_default(),
+        }))
+    }
+
+    pub fn try_build(&self) -> Result<(ServerConfig, Arc<CertResolver>)> {
+        let resolver = Arc::new(CertResolver {
+            cert_file: self.cert_file.clone(),
+            key_file: self.key_file.clone(),
+            sni_dir: self.sni_dir.clone(),
+            store: RwLock::new(Arc::new(CertStore::default())),
+        });
+        resolver.reload(true)?;
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone() as Arc<dyn ResolvesServerCert>);
+        Ok((config, resolver))
+    }
+
+    pub fn spawn_reloader(&self, resolver: Arc<CertResolver>) {
+        let period = self.reload_interval;
+        if period.is_zero() {
+            return;
+        }
+
+        ::actix_web::rt::spawn(async move {
+            let mut interval = ::actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = resolver.reload(false) {
+                    warn!("failed to reload TLS certificates: {e}");
+                }
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+struct CertStore {
+    default: Option<Arc<CertifiedKey>>,
+    sni: BTreeMap<String, Arc<CertifiedKey>>,
+    modified: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl CertStore {
+    fn find(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        self.sni
+            .get(&server_name)
+            .or_else(|| {
+                server_name
+                    .split_once('.')
+                    .and_then(|(_, parent)| self.sni.get(&format!("*.{parent}")))
+            })
+            .cloned()
+    }
+}
+
+pub struct CertResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    sni_dir: Option<PathBuf>,
+    store: RwLock<Arc<CertStore>>,
+}
+
+impl CertResolver {
+    fn reload(&self, force: bool) -> Result<()> {
+        let files = self.list_files()?;
+        let modified = files
+            .iter()
+            .flat_map(|(_, cert_file, key_file)| [cert_file, key_file])
+            .map(|path| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .map(|modified| (path.clone(), modified))
+                    .map_err(|e| anyhow!("failed to stat {path}: {e}", path = path.display()))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        if !force && self.store.read().unwrap().modified == modified {
+            return Ok(());
+        }
+
+        let mut store = CertStore {
+            modified,
+            ..Default::default()
+        };
+        for (server_name, cert_file, key_file) in files {
+            let key = load_certified_key(&cert_file, &key_file)?;
+            match server_name {
+                Some(server_name) => {
+                    store.sni.insert(server_name, key);
+                }
+                None => store.default = Some(key),
+            }
+        }
+
+        info!(
+            "loaded TLS certificates (default + {num_sni} SNI)",
+            num_sni = store.sni.len(),
+        );
+        *self.store.write().unwrap() = Arc::new(store);
+        Ok(())
+    }
+
+    fn list_files(&self) -> Result<Vec<(Option<String>, PathBuf, PathBuf)>> {
+        let mut files = vec![(None, self.cert_file.clone(), self.key_file.clone())];
+
+        if let Some(sni_dir) = &self.sni_dir {
+            let entries = fs::read_dir(sni_dir).map_err(|e| {
+                anyhow!(
+                    "failed to read TLS SNI directory ({path}): {e}",
+                    path = sni_dir.display(),
+                )
+            })?;
+            for entry in entries {
+                let path = entry?.path();
+                // NOTE: skip the hidden directories created by kubernetes volume mounts
+                match path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) if path.is_dir() && !name.starts_with('.') => {
+                        files.push((
+                            Some(name.to_ascii_lowercase()),
+                            path.join(SNI_CERT_FILE),
+                            path.join(SNI_KEY_FILE),
+                        ));
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let store = self.store.read().ok()?.clone();
+        client_hello
+            .server_name()
+            .and_then(|server_name| store.find(server_name))
+            .or_else(|| store.default.clone())
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<::rustls_pemfile::Item>> {
+    let file = fs::File::open(path)
+        .map_err(|e| anyhow!("failed to open {path}: {e}", path = path.display()))?;
+    ::rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| anyhow!("failed to parse {path}: {e}", path = path.display()))
+}
+
+pub fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            ::rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        bail!("no certificates found: {path}", path = path.display());
+    }
+    Ok(certs)
+}
+
+pub fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            ::rustls_pemfile::Item::RSAKey(der)
+            | ::rustls_pemfile::Item::PKCS8Key(der)
+            | ::rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key found: {path}", path = path.display()))
+}
+
+fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<Arc<CertifiedKey>> {
+    let certs = read_certs(cert_file)?;
+    let key = read_private_key(key_file)?;
+    let key = sign::any_supported_type(&key).map_err(|e| {
+        anyhow!(
+            "unsupported private key ({path}): {e}",
+            path = key_file.display()
+        )
+    })?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+pub async fn redirect(tls_addr: web::Data<SocketAddr>, req: HttpRequest) -> impl Responder {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    let host = match host.rsplit_once(':') {
+        // NOTE: do not split IPv6 addresses without ports
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    let port = match tls_addr.port() {
+        443 => Default::default(),
+        port => format!(":{port}"),
+    };
+    let uri = req.uri();
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("https://{host}{port}{path}")))
+        .finish()
+}